@@ -0,0 +1,104 @@
+use serde_json::Value;
+
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+
+/// Kinds of events written to `audit_events`. Stored as their snake_case name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEventType {
+    UserRegistered,
+    Login,
+    LoginCodeRequested,
+    Logout,
+    TokenRefresh,
+    PasswordResetRequested,
+    PasswordReset,
+    PasswordChanged,
+    EmailVerified,
+    SigningKeyRotated,
+    ProfileUpdated,
+    AccountDeactivated,
+    AccountRestored,
+    AccountPurged,
+    RoleGranted,
+    RoleRevoked,
+    PrivacySettingsUpdated,
+    DataExported,
+}
+
+impl AuditEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventType::UserRegistered => "user_registered",
+            AuditEventType::Login => "login",
+            AuditEventType::LoginCodeRequested => "login_code_requested",
+            AuditEventType::Logout => "logout",
+            AuditEventType::TokenRefresh => "token_refresh",
+            AuditEventType::PasswordResetRequested => "password_reset_requested",
+            AuditEventType::PasswordReset => "password_reset",
+            AuditEventType::PasswordChanged => "password_changed",
+            AuditEventType::EmailVerified => "email_verified",
+            AuditEventType::SigningKeyRotated => "signing_key_rotated",
+            AuditEventType::ProfileUpdated => "profile_updated",
+            AuditEventType::AccountDeactivated => "account_deactivated",
+            AuditEventType::AccountRestored => "account_restored",
+            AuditEventType::AccountPurged => "account_purged",
+            AuditEventType::RoleGranted => "role_granted",
+            AuditEventType::RoleRevoked => "role_revoked",
+            AuditEventType::PrivacySettingsUpdated => "privacy_settings_updated",
+            AuditEventType::DataExported => "data_exported",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+}
+
+/// Appends an event to `audit_events`. `actor` is the account the event is
+/// about, when known. Metadata must never carry secrets (passwords, tokens)
+/// or an email address in the clear; use `email_hash` instead.
+pub async fn record(db: &Database, actor: Option<i64>, event_type: AuditEventType, outcome: AuditOutcome, metadata: Value) -> Result<(), AppError> {
+    sqlx::query("INSERT INTO audit_events (actor_user_id, event_type, outcome, metadata) VALUES (?, ?, ?, ?)")
+        .bind(actor)
+        .bind(event_type.as_str())
+        .bind(outcome.as_str())
+        .bind(metadata.to_string())
+        .execute(db.get_pool())
+        .await?;
+
+    Ok(())
+}
+
+/// Keyed hash identifying an email address without storing it, so records
+/// that outlive an account (audit events, purges) keep nothing personal.
+pub fn email_hash(keys: &KeyStore, email: &str) -> String {
+    keys.hash_token(&email.trim().to_lowercase())
+}
+
+/// Deletes events older than the `audit_retention_days` setting. Returns how
+/// many were removed.
+pub async fn prune(db: &Database) -> Result<u64, sqlx::Error> {
+    let retention_days: i64 = db.get_setting("audit_retention_days").await?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(365);
+
+    let pruned = sqlx::query("DELETE FROM audit_events WHERE created_at < datetime('now', ?)")
+        .bind(format!("-{} days", retention_days.max(1)))
+        .execute(db.get_pool())
+        .await?;
+
+    Ok(pruned.rows_affected())
+}
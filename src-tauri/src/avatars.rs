@@ -0,0 +1,249 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader, Limits};
+use tauri::http::{Request, Response, StatusCode};
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+pub const PROTOCOL: &str = "avatar";
+pub const MAX_UPLOAD_BYTES: usize = 5 * 1024 * 1024;
+const MAX_DIMENSION: u32 = 4096;
+const ACCEPTED_FORMATS: &[ImageFormat] = &[ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP, ImageFormat::Gif];
+/// Square sizes rendered for every upload; the first one is the default
+pub const SIZES: &[u32] = &[256, 64];
+
+/// Avatar images under `avatars/` in the app data directory, served to the
+/// webview through the `avatar://` protocol. Files are named
+/// `<user id>-<uuid>-<size>.png`, so everything belonging to one user can
+/// be found by prefix.
+#[derive(Clone)]
+pub struct AvatarStore {
+    dir: PathBuf,
+}
+
+impl AvatarStore {
+    pub fn new(app_handle: &AppHandle) -> Result<Self> {
+        let dir = app_handle
+            .path()
+            .app_data_dir()
+            .expect("Failed to get app data directory")
+            .join("avatars");
+        fs::create_dir_all(&dir)?;
+
+        Ok(AvatarStore { dir })
+    }
+
+    /// A store in `dir`, which must exist before anything is saved.
+    #[cfg(test)]
+    pub fn in_dir(dir: PathBuf) -> Self {
+        AvatarStore { dir }
+    }
+
+    /// Validates an uploaded image, renders every size in `SIZES` and
+    /// returns the URL to store in `users.avatar_url`. Re-encoding from
+    /// decoded pixels drops EXIF and any other embedded metadata.
+    /// CPU-heavy; call it off the async runtime.
+    pub fn save(&self, user_id: i64, bytes: &[u8]) -> Result<String, AppError> {
+        if bytes.is_empty() {
+            return Err(AppError::Validation("Image is empty".to_string()));
+        }
+        if bytes.len() > MAX_UPLOAD_BYTES {
+            return Err(AppError::Validation(format!("Image must be at most {} MB", MAX_UPLOAD_BYTES / (1024 * 1024))));
+        }
+
+        let mut reader = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+
+        if !reader.format().is_some_and(|format| ACCEPTED_FORMATS.contains(&format)) {
+            return Err(AppError::Validation("Image must be PNG, JPEG, WebP or GIF".to_string()));
+        }
+
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_DIMENSION);
+        limits.max_image_height = Some(MAX_DIMENSION);
+        reader.limits(limits);
+
+        let image = reader.decode()
+            .map_err(|e| AppError::Validation(format!("Could not read image: {}", e)))?;
+
+        let stem = format!("{}-{}", user_id, Uuid::new_v4().simple());
+        for size in SIZES {
+            let mut encoded = Cursor::new(Vec::new());
+            image.resize_to_fill(*size, *size, FilterType::Lanczos3)
+                .write_to(&mut encoded, ImageFormat::Png)
+                .map_err(|e| AppError::Internal(format!("Could not encode avatar: {}", e)))?;
+
+            fs::write(self.dir.join(format!("{}-{}.png", stem, size)), encoded.into_inner())
+                .map_err(|e| AppError::Internal(format!("Could not store avatar: {}", e)))?;
+        }
+
+        Ok(url_for(&stem))
+    }
+
+    /// Deletes a user's stored files, except those of the avatar `keep_url`
+    /// points at. Problems are ignored; a stray file is not worth failing for.
+    pub fn remove_for_user(&self, user_id: i64, keep_url: Option<&str>) {
+        let prefix = format!("{}-", user_id);
+        let keep = keep_url.and_then(stem_from_url);
+
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let owned = name.starts_with(&prefix);
+            let kept = keep.as_deref().is_some_and(|stem| name.starts_with(&format!("{}-", stem)));
+
+            if owned && !kept {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Handler for `avatar://localhost/<stem>?size=<n>`.
+    pub fn serve(&self, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+        let stem = request.uri().path().trim_start_matches('/');
+        let size = request.uri().query()
+            .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("size=")))
+            .and_then(|size| size.parse::<u32>().ok())
+            .unwrap_or(SIZES[0]);
+
+        // Only names this store generates; nothing that could leave the directory
+        let valid = !stem.is_empty() && stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        let bytes = if valid && SIZES.contains(&size) {
+            fs::read(self.dir.join(format!("{}-{}.png", stem, size)))
+        } else {
+            Err(std::io::ErrorKind::NotFound.into())
+        };
+
+        match bytes {
+            Ok(bytes) => Response::builder()
+                .header("Content-Type", "image/png")
+                .header("Cache-Control", "max-age=31536000, immutable")
+                .body(bytes)
+                .unwrap(),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Vec::new())
+                .unwrap(),
+        }
+    }
+}
+
+/// Whether an `avatar_url` points at a file in this store.
+pub fn is_stored_avatar(url: &str) -> bool {
+    stem_from_url(url).is_some()
+}
+
+// Windows and Android webviews only reach custom protocols as http://<scheme>.localhost
+fn url_for(stem: &str) -> String {
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{}.localhost/{}", PROTOCOL, stem)
+    } else {
+        format!("{}://localhost/{}", PROTOCOL, stem)
+    }
+}
+
+fn stem_from_url(url: &str) -> Option<String> {
+    let path = url.strip_prefix(&format!("{}://localhost/", PROTOCOL))
+        .or_else(|| url.strip_prefix(&format!("http://{}.localhost/", PROTOCOL)))?;
+    let stem = path.split(['?', '#']).next()?;
+
+    (!stem.is_empty()).then(|| stem.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    fn scratch_store() -> (AvatarStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("progress2win-avatars-{}", Uuid::new_v4().simple()));
+        fs::create_dir(&dir).unwrap();
+        (AvatarStore::in_dir(dir.clone()), dir)
+    }
+
+    fn encoded(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height)).write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    fn stored_files(dir: &PathBuf) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn get(store: &AvatarStore, uri: &str) -> StatusCode {
+        store.serve(&Request::builder().uri(uri).body(Vec::new()).unwrap()).status()
+    }
+
+    #[test]
+    fn rejects_anything_but_reasonably_sized_images_in_accepted_formats() {
+        let (store, dir) = scratch_store();
+
+        let mut too_big = encoded(8, 8, ImageFormat::Png);
+        too_big.resize(MAX_UPLOAD_BYTES + 1, 0);
+        let mut bitmap = b"BM".to_vec();
+        bitmap.resize(64, 0);
+
+        for bytes in [Vec::new(), too_big, b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>".to_vec(), bitmap, encoded(MAX_DIMENSION + 1, 1, ImageFormat::Png)] {
+            assert!(matches!(store.save(1, &bytes), Err(AppError::Validation(_))));
+        }
+        assert!(stored_files(&dir).is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stores_every_size_and_serves_only_names_it_generated() {
+        let (store, dir) = scratch_store();
+
+        let url = store.save(7, &encoded(300, 200, ImageFormat::Jpeg)).unwrap();
+        assert!(is_stored_avatar(&url));
+        let stem = stem_from_url(&url).unwrap();
+        assert_eq!(stored_files(&dir), vec![format!("{}-256.png", stem), format!("{}-64.png", stem)]);
+
+        let thumbnail = image::load_from_memory(&fs::read(dir.join(format!("{}-64.png", stem))).unwrap()).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (64, 64));
+
+        assert_eq!(get(&store, &format!("avatar://localhost/{}", stem)), StatusCode::OK);
+        assert_eq!(get(&store, &format!("avatar://localhost/{}?size=64", stem)), StatusCode::OK);
+        assert_eq!(get(&store, &format!("avatar://localhost/{}?size=100", stem)), StatusCode::NOT_FOUND);
+        assert_eq!(get(&store, "avatar://localhost/..%2F..%2Fkeys"), StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removes_only_the_users_other_avatars() {
+        let (store, dir) = scratch_store();
+        let image = encoded(16, 16, ImageFormat::Png);
+
+        let old = store.save(1, &image).unwrap();
+        let current = store.save(1, &image).unwrap();
+        let other_user = store.save(11, &image).unwrap();
+
+        store.remove_for_user(1, Some(&current));
+        let remaining = stored_files(&dir);
+        assert_eq!(remaining.len(), 4);
+        assert!(!remaining.iter().any(|name| name.starts_with(&stem_from_url(&old).unwrap())));
+        assert!(remaining.iter().any(|name| name.starts_with(&stem_from_url(&other_user).unwrap())));
+
+        store.remove_for_user(1, None);
+        assert_eq!(stored_files(&dir).len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
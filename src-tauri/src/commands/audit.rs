@@ -0,0 +1,103 @@
+use tauri::State;
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::models::*;
+use crate::roles::{role_of, Permission};
+use crate::session::authenticate;
+
+const MAX_PAGE_SIZE: i64 = 200;
+
+// Audit commands
+/// Security events, newest first. Callers who may view the audit log see
+/// every event, including those with no known actor, unless they filter by
+/// `actor_user_id`; everyone else only ever sees their own events.
+#[tauri::command]
+pub async fn get_audit_events(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    filter: Option<AuditEventFilter>,
+) -> Result<AuditEventPage, String> {
+    let caller_id = authenticate(&db, &keys, &access_token).await?.user_id;
+    let filter = filter.unwrap_or_default();
+
+    let actor_user_id = if role_of(&db, caller_id).await?.allows(Permission::ViewAuditLog) {
+        filter.actor_user_id
+    } else if filter.actor_user_id.is_none_or(|actor| actor == caller_id) {
+        Some(caller_id)
+    } else {
+        return Err(AppError::Unauthorized("You do not have permission to do this".to_string()).into());
+    };
+
+    let mut conditions = " WHERE 1 = 1".to_string();
+    if actor_user_id.is_some() {
+        conditions.push_str(" AND actor_user_id = ?");
+    }
+    if filter.event_type.is_some() {
+        conditions.push_str(" AND event_type = ?");
+    }
+    if filter.outcome.is_some() {
+        conditions.push_str(" AND outcome = ?");
+    }
+    if filter.since.is_some() {
+        conditions.push_str(" AND created_at >= datetime(?)");
+    }
+    if filter.until.is_some() {
+        conditions.push_str(" AND created_at < datetime(?)");
+    }
+
+    let count_query = format!("SELECT COUNT(*) as count FROM audit_events{}", conditions);
+    let page_query = format!("SELECT * FROM audit_events{} ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?", conditions);
+
+    let mut count_builder = sqlx::query(&count_query);
+    let mut page_builder = sqlx::query(&page_query);
+
+    if let Some(actor_user_id) = actor_user_id {
+        count_builder = count_builder.bind(actor_user_id);
+        page_builder = page_builder.bind(actor_user_id);
+    }
+
+    if let Some(event_type) = &filter.event_type {
+        count_builder = count_builder.bind(event_type);
+        page_builder = page_builder.bind(event_type);
+    }
+    if let Some(outcome) = &filter.outcome {
+        count_builder = count_builder.bind(outcome);
+        page_builder = page_builder.bind(outcome);
+    }
+    if let Some(since) = filter.since {
+        count_builder = count_builder.bind(since);
+        page_builder = page_builder.bind(since);
+    }
+    if let Some(until) = filter.until {
+        count_builder = count_builder.bind(until);
+        page_builder = page_builder.bind(until);
+    }
+
+    let total: i64 = count_builder
+        .fetch_one(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?
+        .get("count");
+
+    let rows = page_builder
+        .bind(filter.limit.unwrap_or(50).clamp(1, MAX_PAGE_SIZE))
+        .bind(filter.offset.unwrap_or(0).max(0))
+        .fetch_all(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let events = rows.into_iter().map(|row| AuditEvent {
+        id: row.get("id"),
+        actor_user_id: row.get("actor_user_id"),
+        event_type: row.get("event_type"),
+        outcome: row.get("outcome"),
+        metadata: serde_json::from_str(&row.get::<String, _>("metadata")).unwrap_or_default(),
+        created_at: row.get("created_at"),
+    }).collect();
+
+    Ok(AuditEventPage { events, total })
+}
@@ -1,19 +1,28 @@
 use tauri::State;
 use sqlx::Row;
-use bcrypt::{hash, verify, DEFAULT_COST};
-use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey};
-use chrono::{Utc, Duration};
+use serde_json::json;
 use uuid::Uuid;
 
+use crate::audit::{self, AuditEventType, AuditOutcome};
+use crate::commands::two_factor::second_factor_enabled;
+use crate::commands::users::{account_can_sign_in, fetch_user, restore_deactivated_account};
 use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::mail::{MailQueue, OutgoingMail};
 use crate::models::*;
-
-const JWT_SECRET: &str = "your-super-secret-jwt-key-change-in-production";
+use crate::password_hashing::PasswordHasher;
+use crate::password_policy::{PasswordPolicy, PasswordViolation, PersonalInfo};
+use crate::roles::{authorize, Permission, NEW_ACCOUNT_ROLE_SQL};
+use crate::session::{authenticate, generate_second_factor_challenge, revoke_all_sessions, revoke_session, rotate_session, session_for_refresh_token, start_session};
+use crate::throttle::LoginThrottle;
 
 // Auth commands
 #[tauri::command]
 pub async fn register_user(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    mail: State<'_, MailQueue>,
     user_data: UserCreate,
 ) -> Result<User, String> {
     // Validate email format
@@ -32,14 +41,23 @@ pub async fn register_user(
         return Err("User already exists".to_string());
     }
 
+    PasswordPolicy::load(&db).await.map_err(|e| e.to_string())?
+        .enforce(&user_data.password, &PersonalInfo {
+            email: &user_data.email,
+            first_name: &user_data.first_name,
+            last_name: &user_data.last_name,
+        })?;
+
     // Hash password
-    let password_hash = hash(&user_data.password, DEFAULT_COST)
-        .map_err(|e| format!("Password hashing failed: {}", e))?;
+    let password_hash = PasswordHasher::load(&db).await.map_err(|e| e.to_string())?
+        .hash(&user_data.password)?;
 
     // Insert user
-    let result = sqlx::query(
-        "INSERT INTO users (email, password_hash, first_name, last_name) VALUES (?, ?, ?, ?)"
-    )
+    let insert_query = format!(
+        "INSERT INTO users (email, password_hash, first_name, last_name, role) VALUES (?, ?, ?, ?, {})",
+        NEW_ACCOUNT_ROLE_SQL
+    );
+    let result = sqlx::query(&insert_query)
     .bind(&user_data.email)
     .bind(&password_hash)
     .bind(&user_data.first_name)
@@ -50,97 +68,159 @@ pub async fn register_user(
 
     let user_id = result.last_insert_rowid();
 
-    // Fetch created user
-    let user_row = sqlx::query("SELECT * FROM users WHERE id = ?")
-        .bind(user_id)
-        .fetch_one(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
+    audit::record(&db, Some(user_id), AuditEventType::UserRegistered, AuditOutcome::Success, json!({})).await?;
 
-    Ok(User {
-        id: user_row.get("id"),
-        email: user_row.get("email"),
-        first_name: user_row.get("first_name"),
-        last_name: user_row.get("last_name"),
-        avatar_url: user_row.get("avatar_url"),
-        goals: serde_json::from_str(&user_row.get::<String, _>("goals")).unwrap_or_default(),
-        is_active: user_row.get("is_active"),
-        email_verified: user_row.get("email_verified"),
-        created_at: user_row.get("created_at"),
-        updated_at: user_row.get("updated_at"),
-    })
+    issue_verification_token(&db, &keys, &mail, user_id).await?;
+
+    fetch_user(&db, user_id).await
 }
 
 #[tauri::command]
 pub async fn login_user(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     login_data: LoginRequest,
-) -> Result<AuthResponse, String> {
-    // Find user
-    let result = sqlx::query("SELECT * FROM users WHERE email = ? AND is_active = 1")
+    client_id: Option<String>,
+    client: Option<SessionClient>,
+) -> Result<LoginResponse, String> {
+    // Throttle before verifying the password so guesses stay expensive
+    let throttle = LoginThrottle::load(&db).await.map_err(|e| e.to_string())?;
+    let mut throttle_keys = vec![LoginThrottle::email_key(&login_data.email)];
+    if let Some(client_id) = client_id.as_deref().filter(|id| !id.is_empty()) {
+        throttle_keys.push(LoginThrottle::client_key(client_id));
+    }
+    for key in &throttle_keys {
+        if let Err(e) = throttle.check(&db, key).await {
+            audit::record(&db, None, AuditEventType::Login, AuditOutcome::Failure, json!({
+                "email_hash": audit::email_hash(&keys, &login_data.email),
+                "client_id": client_id,
+                "reason": "throttled",
+            })).await?;
+            return Err(e.into());
+        }
+    }
+
+    // Find user; deactivated accounts are included so signing in can restore them
+    let result = sqlx::query("SELECT * FROM users WHERE email = ?")
         .bind(&login_data.email)
         .fetch_optional(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
+    // Verify password
+    let hasher = PasswordHasher::load(&db).await.map_err(|e| e.to_string())?;
+    let is_valid = match &result {
+        Some(user_row) => hasher.verify(&login_data.password, user_row.get("password_hash"))?,
+        None => {
+            hasher.verify_dummy(&login_data.password);
+            false
+        }
+    };
+
     let user_row = match result {
-        Some(row) => row,
-        None => return Err("Invalid credentials".to_string()),
+        Some(row) if is_valid => row,
+        result => {
+            let email_locked = throttle.record_failure(&db, &throttle_keys[0]).await?;
+            for key in &throttle_keys[1..] {
+                throttle.record_failure(&db, key).await?;
+            }
+
+            // Tell the account owner when their email just got locked
+            if let (true, Some(row)) = (email_locked, &result) {
+                sqlx::query("INSERT INTO notifications (user_id, title, message, type) VALUES (?, ?, ?, 'warning')")
+                    .bind(row.get::<i64, _>("id"))
+                    .bind("Sign-in temporarily locked")
+                    .bind("Too many failed sign-in attempts were made on your account, so sign-in has been locked for a while. If this wasn't you, consider changing your password.")
+                    .execute(db.get_pool())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+
+            audit::record(&db, result.as_ref().map(|row| row.get("id")), AuditEventType::Login, AuditOutcome::Failure, json!({
+                "email_hash": audit::email_hash(&keys, &login_data.email),
+                "client_id": client_id,
+                "reason": if result.is_some() { "wrong_password" } else { "unknown_email" },
+                "locked": email_locked,
+            })).await?;
+
+            return Err("Invalid credentials".to_string());
+        }
     };
 
-    // Verify password
-    let password_hash: String = user_row.get("password_hash");
-    let is_valid = verify(&login_data.password, &password_hash)
-        .map_err(|e| format!("Password verification failed: {}", e))?;
+    throttle.record_success(&db, &throttle_keys[0]).await?;
 
-    if !is_valid {
+    let user_id: i64 = user_row.get("id");
+    if !account_can_sign_in(&db, user_id).await? {
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Failure, json!({
+            "client_id": client_id,
+            "reason": "account_inactive",
+        })).await?;
         return Err("Invalid credentials".to_string());
     }
 
-    let user = User {
-        id: user_row.get("id"),
-        email: user_row.get("email"),
-        first_name: user_row.get("first_name"),
-        last_name: user_row.get("last_name"),
-        avatar_url: user_row.get("avatar_url"),
-        goals: serde_json::from_str(&user_row.get::<String, _>("goals")).unwrap_or_default(),
-        is_active: user_row.get("is_active"),
-        email_verified: user_row.get("email_verified"),
-        created_at: user_row.get("created_at"),
-        updated_at: user_row.get("updated_at"),
-    };
+    // Upgrade bcrypt or outdated Argon2 hashes while the plaintext is at hand
+    if hasher.needs_rehash(user_row.get("password_hash")) {
+        sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+            .bind(hasher.hash(&login_data.password)?)
+            .bind(user_id)
+            .execute(db.get_pool())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
 
-    // Generate tokens
-    let access_token = generate_access_token(user.id)?;
-    let refresh_token = generate_refresh_token(user.id)?;
+    // Accounts with two-factor authentication get a challenge, not tokens
+    if second_factor_enabled(&db, user_id).await? {
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Success, json!({
+            "client_id": client_id,
+            "second_factor_required": true,
+        })).await?;
+        return Ok(LoginResponse::SecondFactorRequired {
+            challenge_token: generate_second_factor_challenge(&keys, user_id)?,
+        });
+    }
 
-    // Store refresh token
-    let expires_at = Utc::now() + Duration::days(30);
-    sqlx::query("INSERT INTO refresh_tokens (user_id, token, expires_at) VALUES (?, ?, ?)")
-        .bind(user.id)
-        .bind(&refresh_token)
-        .bind(expires_at)
-        .execute(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
+    // Signing in during the grace period cancels a pending deletion, but
+    // only once every factor has been checked
+    if !user_row.get::<bool, _>("is_active") {
+        restore_deactivated_account(&db, user_id).await?;
+    }
 
-    Ok(AuthResponse {
-        access_token,
-        refresh_token,
+    let user = fetch_user(&db, user_id).await?;
+
+    let session = start_session(&db, &keys, user.id, &client.unwrap_or_default()).await?;
+
+    audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Success, json!({
+        "client_id": client_id,
+        "session_id": session.session_id,
+    })).await?;
+
+    Ok(LoginResponse::Authenticated(Box::new(AuthResponse {
+        access_token: session.access_token,
+        refresh_token: session.refresh_token,
         user,
-    })
+    })))
 }
 
 #[tauri::command]
 pub async fn logout_user(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     refresh_token: String,
+    all_sessions: Option<bool>,
 ) -> Result<(), String> {
-    sqlx::query("DELETE FROM refresh_tokens WHERE token = ?")
-        .bind(&refresh_token)
-        .execute(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
+    let (user_id, session_id) = session_for_refresh_token(&db, &keys, &refresh_token).await?;
+
+    let all_sessions = all_sessions.unwrap_or(false);
+    if all_sessions {
+        revoke_all_sessions(&db, user_id, None, "logout").await?;
+    } else {
+        revoke_session(&db, user_id, session_id, "logout").await?;
+    }
+
+    audit::record(&db, Some(user_id), AuditEventType::Logout, AuditOutcome::Success, json!({
+        "session_id": session_id,
+        "all_sessions": all_sessions,
+    })).await?;
 
     Ok(())
 }
@@ -148,67 +228,29 @@ pub async fn logout_user(
 #[tauri::command]
 pub async fn refresh_token(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     refresh_token: String,
 ) -> Result<AuthResponse, String> {
-    // Verify refresh token
-    let token_data = decode::<serde_json::Value>(
-        &refresh_token,
-        &DecodingKey::from_secret(JWT_SECRET.as_ref()),
-        &Validation::new(Algorithm::HS256),
-    ).map_err(|_| "Invalid refresh token".to_string())?;
-
-    let user_id = token_data.claims.get("user_id")
-        .and_then(|v| v.as_i64())
-        .ok_or_else(|| "Invalid token format".to_string())?;
-
-    // Check if refresh token exists in database
-    let token_row = sqlx::query("SELECT user_id FROM refresh_tokens WHERE token = ? AND expires_at > datetime('now')")
-        .bind(&refresh_token)
-        .fetch_optional(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
-
-    if token_row.is_none() {
-        return Err("Invalid refresh token".to_string());
-    }
-
-    // Get user
-    let user_row = sqlx::query("SELECT * FROM users WHERE id = ? AND is_active = 1")
-        .bind(user_id)
-        .fetch_one(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let user = User {
-        id: user_row.get("id"),
-        email: user_row.get("email"),
-        first_name: user_row.get("first_name"),
-        last_name: user_row.get("last_name"),
-        avatar_url: user_row.get("avatar_url"),
-        goals: serde_json::from_str(&user_row.get::<String, _>("goals")).unwrap_or_default(),
-        is_active: user_row.get("is_active"),
-        email_verified: user_row.get("email_verified"),
-        created_at: user_row.get("created_at"),
-        updated_at: user_row.get("updated_at"),
+    let (user_id, session) = match rotate_session(&db, &keys, &refresh_token).await {
+        Ok(rotated) => rotated,
+        Err(e) => {
+            // Attribute the failure when the token at least carries a valid signature
+            let actor = session_for_refresh_token(&db, &keys, &refresh_token).await.ok().map(|(user_id, _)| user_id);
+            audit::record(&db, actor, AuditEventType::TokenRefresh, AuditOutcome::Failure, json!({ "reason": e.to_string() })).await?;
+            return Err(e.into());
+        }
     };
 
-    // Generate new tokens
-    let new_access_token = generate_access_token(user.id)?;
-    let new_refresh_token = generate_refresh_token(user.id)?;
+    audit::record(&db, Some(user_id), AuditEventType::TokenRefresh, AuditOutcome::Success, json!({ "session_id": session.session_id })).await?;
 
-    // Update refresh token in database
-    let expires_at = Utc::now() + Duration::days(30);
-    sqlx::query("UPDATE refresh_tokens SET token = ?, expires_at = ? WHERE token = ?")
-        .bind(&new_refresh_token)
-        .bind(expires_at)
-        .bind(&refresh_token)
-        .execute(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
+    let user = fetch_user(&db, user_id).await?;
+    if !user.is_active {
+        return Err(AppError::Unauthorized("This account is deactivated".to_string()).into());
+    }
 
     Ok(AuthResponse {
-        access_token: new_access_token,
-        refresh_token: new_refresh_token,
+        access_token: session.access_token,
+        refresh_token: session.refresh_token,
         user,
     })
 }
@@ -216,37 +258,40 @@ pub async fn refresh_token(
 #[tauri::command]
 pub async fn forgot_password(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    mail: State<'_, MailQueue>,
     email: String,
 ) -> Result<(), String> {
     // Check if user exists
-    let user_row = sqlx::query("SELECT id FROM users WHERE email = ?")
+    let user_row = sqlx::query("SELECT id, first_name FROM users WHERE email = ?")
         .bind(&email)
         .fetch_optional(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
-    if user_row.is_none() {
+    let user_row = match user_row {
+        Some(row) => row,
         // Don't reveal if email exists or not
-        return Ok(());
-    }
+        None => return Ok(()),
+    };
 
-    let user_id: i64 = user_row.unwrap().get::<i64, _>("id");
+    let user_id: i64 = user_row.get("id");
 
     // Generate reset token
     let reset_token = Uuid::new_v4().to_string();
-    let expires_at = Utc::now() + Duration::hours(1);
 
-    // Store reset token
-    sqlx::query("INSERT INTO password_reset_tokens (user_id, token, expires_at) VALUES (?, ?, ?)")
+    // Store only the keyed hash of the reset token
+    sqlx::query("INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES (?, ?, datetime('now', '+1 hours'))")
         .bind(user_id)
-        .bind(&reset_token)
-        .bind(expires_at)
+        .bind(keys.hash_token(&reset_token))
         .execute(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
-    // TODO: Send email with reset link
-    println!("Password reset token for {}: {}", email, reset_token);
+    audit::record(&db, Some(user_id), AuditEventType::PasswordResetRequested, AuditOutcome::Success, json!({})).await?;
+
+    // The raw token only ever reaches the account's mailbox
+    mail.enqueue(OutgoingMail::password_reset(&email, &user_row.get::<String, _>("first_name"), &reset_token));
 
     Ok(())
 }
@@ -254,43 +299,171 @@ pub async fn forgot_password(
 #[tauri::command]
 pub async fn reset_password(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     token: String,
     password: String,
 ) -> Result<(), String> {
     // Find valid reset token
-    let token_row = sqlx::query("SELECT user_id FROM password_reset_tokens WHERE token = ? AND expires_at > datetime('now') AND used = 0")
-        .bind(&token)
+    let token_row = sqlx::query("SELECT id, user_id FROM password_reset_tokens WHERE token_hash = ? AND expires_at > datetime('now') AND used = 0")
+        .bind(keys.hash_token(&token))
         .fetch_optional(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
-    let user_id: i64 = match token_row {
-        Some(row) => row.get::<i64, _>("user_id"),
-        None => return Err("Invalid or expired reset token".to_string()),
+    let (token_id, user_id): (i64, i64) = match token_row {
+        Some(row) => (row.get("id"), row.get("user_id")),
+        None => {
+            audit::record(&db, None, AuditEventType::PasswordReset, AuditOutcome::Failure, json!({ "reason": "invalid_token" })).await?;
+            return Err("Invalid or expired reset token".to_string());
+        }
     };
 
+    let user_row = sqlx::query("SELECT email, first_name, last_name FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    PasswordPolicy::load(&db).await.map_err(|e| e.to_string())?
+        .enforce(&password, &PersonalInfo {
+            email: user_row.get("email"),
+            first_name: user_row.get("first_name"),
+            last_name: user_row.get("last_name"),
+        })?;
+
     // Hash new password
-    let password_hash = hash(&password, DEFAULT_COST)
-        .map_err(|e| format!("Password hashing failed: {}", e))?;
+    let password_hash = PasswordHasher::load(&db).await.map_err(|e| e.to_string())?
+        .hash(&password)?;
+
+    // Claiming the token and changing the password happen together, and
+    // only one of two concurrent resets with the same token can claim it
+    let mut tx = db.get_pool().begin().await.map_err(|e| e.to_string())?;
 
-    // Update password
-    sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+    let claimed = sqlx::query("UPDATE password_reset_tokens SET used = 1 WHERE id = ? AND used = 0 AND expires_at > datetime('now')")
+        .bind(token_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if claimed.rows_affected() == 0 {
+        drop(tx);
+        audit::record(&db, Some(user_id), AuditEventType::PasswordReset, AuditOutcome::Failure, json!({ "reason": "invalid_token" })).await?;
+        return Err("Invalid or expired reset token".to_string());
+    }
+
+    sqlx::query("UPDATE users SET password_hash = ?, updated_at = datetime('now') WHERE id = ?")
         .bind(&password_hash)
-        .bind(&user_id)
-        .execute(db.get_pool())
+        .bind(user_id)
+        .execute(&mut *tx)
         .await
         .map_err(|e| e.to_string())?;
 
-    // Mark token as used
-    sqlx::query("UPDATE password_reset_tokens SET used = 1 WHERE token = ?")
-        .bind(&token)
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    // Sign out every session for this user
+    let signed_out = revoke_all_sessions(&db, user_id, None, "password_reset").await?;
+
+    if signed_out > 0 {
+        sqlx::query("INSERT INTO notifications (user_id, title, message, type) VALUES (?, ?, ?, 'info')")
+            .bind(user_id)
+            .bind("Password reset")
+            .bind(format!("Your password was reset and {} active session(s) were signed out.", signed_out))
+            .execute(db.get_pool())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    audit::record(&db, Some(user_id), AuditEventType::PasswordReset, AuditOutcome::Success, json!({ "sessions_revoked": signed_out })).await?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_current_user(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+) -> Result<User, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    fetch_user(&db, user_id).await
+}
+
+#[tauri::command]
+pub async fn rotate_signing_key(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    revoke_previous: Option<bool>,
+) -> Result<String, String> {
+    let user_id = authorize(&db, &keys, &access_token, Permission::ManageSigningKeys).await?.user_id;
+
+    let revoke_previous = revoke_previous.unwrap_or(false);
+    let kid = keys.rotate(revoke_previous)
+        .map_err(|e| format!("Key rotation failed: {}", e))?;
+
+    audit::record(&db, Some(user_id), AuditEventType::SigningKeyRotated, AuditOutcome::Success, json!({
+        "kid": kid,
+        "revoke_previous": revoke_previous,
+    })).await?;
+
+    Ok(kid)
+}
+
+#[tauri::command]
+pub async fn change_password(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    current_password: String,
+    new_password: String,
+) -> Result<(), String> {
+    let auth = authenticate(&db, &keys, &access_token).await?;
+
+    let user_row = sqlx::query("SELECT email, first_name, last_name, password_hash FROM users WHERE id = ?")
+        .bind(auth.user_id)
+        .fetch_one(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Verify current password
+    let hasher = PasswordHasher::load(&db).await.map_err(|e| e.to_string())?;
+    let is_valid = hasher.verify(&current_password, user_row.get("password_hash"))?;
+
+    if !is_valid {
+        audit::record(&db, Some(auth.user_id), AuditEventType::PasswordChanged, AuditOutcome::Failure, json!({ "reason": "wrong_password" })).await?;
+        return Err(AppError::Unauthorized("Current password is incorrect".to_string()).into());
+    }
+
+    if new_password == current_password {
+        return Err(AppError::Validation("New password must differ from the current one".to_string()).into());
+    }
+    PasswordPolicy::load(&db).await.map_err(|e| e.to_string())?
+        .enforce(&new_password, &PersonalInfo {
+            email: user_row.get("email"),
+            first_name: user_row.get("first_name"),
+            last_name: user_row.get("last_name"),
+        })?;
+
+    // Hash new password
+    let new_hash = hasher.hash(&new_password)?;
+
+    sqlx::query("UPDATE users SET password_hash = ?, updated_at = datetime('now') WHERE id = ?")
+        .bind(&new_hash)
+        .bind(auth.user_id)
         .execute(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
-    // Remove all refresh tokens for this user
-    sqlx::query("DELETE FROM refresh_tokens WHERE user_id = ?")
-        .bind(&user_id)
+    // Keep the caller signed in but end every other session
+    let signed_out = revoke_all_sessions(&db, auth.user_id, Some(auth.session_id), "password_change").await?;
+
+    audit::record(&db, Some(auth.user_id), AuditEventType::PasswordChanged, AuditOutcome::Success, json!({ "sessions_revoked": signed_out })).await?;
+
+    sqlx::query("INSERT INTO notifications (user_id, title, message, type) VALUES (?, ?, ?, 'info')")
+        .bind(auth.user_id)
+        .bind("Password changed")
+        .bind("Your password was changed and your other sessions were signed out. If this wasn't you, reset your password immediately.")
         .execute(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
@@ -299,56 +472,308 @@ pub async fn reset_password(
 }
 
 #[tauri::command]
-pub async fn get_current_user(
+pub async fn verify_email(
     db: State<'_, Database>,
-    user_id: i64,
-) -> Result<User, String> {
-    let user_row = sqlx::query("SELECT * FROM users WHERE id = ?")
+    keys: State<'_, KeyStore>,
+    token: String,
+) -> Result<(), String> {
+    // Find valid verification token
+    let token_row = sqlx::query("SELECT id, user_id FROM email_verification_tokens WHERE token_hash = ? AND expires_at > datetime('now') AND used = 0")
+        .bind(keys.hash_token(&token))
+        .fetch_optional(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let (token_id, user_id): (i64, i64) = match token_row {
+        Some(row) => (row.get("id"), row.get("user_id")),
+        None => return Err("Invalid or expired verification token".to_string()),
+    };
+
+    let mut tx = db.get_pool().begin().await.map_err(|e| e.to_string())?;
+
+    // Mark token as used, unless a concurrent request already did
+    let claimed = sqlx::query("UPDATE email_verification_tokens SET used = 1 WHERE id = ? AND used = 0")
+        .bind(token_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if claimed.rows_affected() == 0 {
+        return Err("Invalid or expired verification token".to_string());
+    }
+
+    sqlx::query("UPDATE users SET email_verified = 1, updated_at = datetime('now') WHERE id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    audit::record(&db, Some(user_id), AuditEventType::EmailVerified, AuditOutcome::Success, json!({})).await?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn resend_verification_email(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    mail: State<'_, MailQueue>,
+    access_token: String,
+) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let user_row = sqlx::query("SELECT email_verified FROM users WHERE id = ?")
         .bind(user_id)
         .fetch_one(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
-    Ok(User {
-        id: user_row.get("id"),
-        email: user_row.get("email"),
-        first_name: user_row.get("first_name"),
-        last_name: user_row.get("last_name"),
-        avatar_url: user_row.get("avatar_url"),
-        goals: serde_json::from_str(&user_row.get::<String, _>("goals")).unwrap_or_default(),
-        is_active: user_row.get("is_active"),
-        email_verified: user_row.get("email_verified"),
-        created_at: user_row.get("created_at"),
-        updated_at: user_row.get("updated_at"),
-    })
+    if user_row.get::<bool, _>("email_verified") {
+        return Err(AppError::Conflict("Email address is already verified".to_string()).into());
+    }
+
+    issue_verification_token(&db, &keys, &mail, user_id).await?;
+
+    Ok(())
+}
+
+/// Lets the registration and password forms show every unmet rule at once.
+#[tauri::command]
+pub async fn check_password_strength(
+    db: State<'_, Database>,
+    password: String,
+    email: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+) -> Result<Vec<PasswordViolation>, String> {
+    let policy = PasswordPolicy::load(&db).await.map_err(|e| e.to_string())?;
+
+    Ok(policy.check(&password, &PersonalInfo {
+        email: email.as_deref().unwrap_or_default(),
+        first_name: first_name.as_deref().unwrap_or_default(),
+        last_name: last_name.as_deref().unwrap_or_default(),
+    }))
 }
 
 // Helper functions
-fn generate_access_token(user_id: i64) -> Result<String, String> {
-    let claims = serde_json::json!({
-        "user_id": user_id,
-        "exp": (Utc::now() + Duration::days(7)).timestamp(),
-        "iat": Utc::now().timestamp(),
-    });
-
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-    ).map_err(|e| format!("Token generation failed: {}", e))
+async fn issue_verification_token(db: &Database, keys: &KeyStore, mail: &MailQueue, user_id: i64) -> Result<(), AppError> {
+    // Only the most recent verification link stays valid
+    sqlx::query("UPDATE email_verification_tokens SET used = 1 WHERE user_id = ? AND used = 0")
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await?;
+
+    let verification_token = Uuid::new_v4().to_string();
+
+    sqlx::query("INSERT INTO email_verification_tokens (user_id, token_hash, expires_at) VALUES (?, ?, datetime('now', '+24 hours'))")
+        .bind(user_id)
+        .bind(keys.hash_token(&verification_token))
+        .execute(db.get_pool())
+        .await?;
+
+    let user_row = sqlx::query("SELECT email, first_name FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(db.get_pool())
+        .await?;
+
+    mail.enqueue(OutgoingMail::email_verification(
+        &user_row.get::<String, _>("email"),
+        &user_row.get::<String, _>("first_name"),
+        &verification_token,
+    ));
+
+    Ok(())
 }
 
-fn generate_refresh_token(user_id: i64) -> Result<String, String> {
-    let claims = serde_json::json!({
-        "user_id": user_id,
-        "type": "refresh",
-        "exp": (Utc::now() + Duration::days(30)).timestamp(),
-        "iat": Utc::now().timestamp(),
-    });
-
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-    ).map_err(|e| format!("Token generation failed: {}", e))
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::Manager;
+
+    use crate::test_support::{self, PASSWORD};
+
+    const NEW_PASSWORD: &str = "Battery-staple-77";
+
+    async fn insert_token(app: &tauri::App<tauri::test::MockRuntime>, table: &str, user_id: i64, token: &str, expires_in: &str) {
+        sqlx::query(&format!("INSERT INTO {} (user_id, token_hash, expires_at) VALUES (?, ?, datetime('now', ?))", table))
+            .bind(user_id)
+            .bind(app.state::<KeyStore>().hash_token(token))
+            .bind(expires_in)
+            .execute(app.state::<Database>().get_pool())
+            .await
+            .unwrap();
+    }
+
+    async fn password_matches(app: &tauri::App<tauri::test::MockRuntime>, user_id: i64, password: &str) -> bool {
+        let db = app.state::<Database>();
+        let hash: String = sqlx::query("SELECT password_hash FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("password_hash");
+
+        PasswordHasher::load(&db).await.unwrap().verify(password, &hash).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forgot_password_stores_only_a_hash_with_an_expiry() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+
+        forgot_password(app.state(), app.state(), app.state(), "nobody@example.com".to_string()).await.unwrap();
+        forgot_password(app.state(), app.state(), app.state(), "jane@example.com".to_string()).await.unwrap();
+
+        let rows = sqlx::query("SELECT user_id, token_hash, expires_at > datetime('now', '+59 minutes') AS fresh FROM password_reset_tokens")
+            .fetch_all(app.state::<Database>().get_pool())
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get::<i64, _>("user_id"), user_id);
+        assert_eq!(rows[0].get::<String, _>("token_hash").len(), 64);
+        assert!(rows[0].get::<bool, _>("fresh"));
+    }
+
+    #[tokio::test]
+    async fn reset_tokens_work_once_and_sign_out_every_session() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
+        insert_token(&app, "password_reset_tokens", user_id, "reset-token", "+1 hours").await;
+
+        reset_password(app.state(), app.state(), "reset-token".to_string(), NEW_PASSWORD.to_string()).await.unwrap();
+        assert!(password_matches(&app, user_id, NEW_PASSWORD).await);
+        assert!(authenticate(&app.state(), &app.state(), &session.access_token).await.is_err());
+
+        let reused = reset_password(app.state(), app.state(), "reset-token".to_string(), PASSWORD.to_string()).await;
+        assert!(reused.is_err());
+        assert!(password_matches(&app, user_id, NEW_PASSWORD).await);
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_reset_tokens() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        insert_token(&app, "password_reset_tokens", user_id, "reset-token", "-1 minutes").await;
+
+        let result = reset_password(app.state(), app.state(), "reset-token".to_string(), NEW_PASSWORD.to_string()).await;
+        assert!(result.is_err());
+        assert!(password_matches(&app, user_id, PASSWORD).await);
+    }
+
+    #[tokio::test]
+    async fn change_password_requires_the_current_password() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
+
+        let wrong = change_password(app.state(), app.state(), session.access_token.clone(), "not-it".to_string(), NEW_PASSWORD.to_string()).await;
+        assert_eq!(wrong.unwrap_err(), String::from(AppError::Unauthorized("Current password is incorrect".to_string())));
+
+        let same = change_password(app.state(), app.state(), session.access_token.clone(), PASSWORD.to_string(), PASSWORD.to_string()).await;
+        assert!(same.is_err());
+        assert!(password_matches(&app, user_id, PASSWORD).await);
+    }
+
+    #[tokio::test]
+    async fn change_password_keeps_only_the_calling_session() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let caller = test_support::sign_in(&app, user_id).await;
+        let other = test_support::sign_in(&app, user_id).await;
+
+        change_password(app.state(), app.state(), caller.access_token.clone(), PASSWORD.to_string(), NEW_PASSWORD.to_string()).await.unwrap();
+
+        assert!(password_matches(&app, user_id, NEW_PASSWORD).await);
+        assert!(authenticate(&app.state(), &app.state(), &caller.access_token).await.is_ok());
+        assert!(authenticate(&app.state(), &app.state(), &other.access_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn change_password_needs_a_valid_access_token() {
+        let app = test_support::app().await;
+        test_support::create_user(&app, "jane@example.com").await;
+
+        let result = change_password(app.state(), app.state(), "forged".to_string(), PASSWORD.to_string(), NEW_PASSWORD.to_string()).await;
+        assert!(result.is_err());
+    }
+
+    async fn email_verified(app: &tauri::App<tauri::test::MockRuntime>, user_id: i64) -> bool {
+        sqlx::query("SELECT email_verified FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(app.state::<Database>().get_pool())
+            .await
+            .unwrap()
+            .get("email_verified")
+    }
+
+    #[tokio::test]
+    async fn verification_tokens_work_once() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        insert_token(&app, "email_verification_tokens", user_id, "verify-token", "+24 hours").await;
+
+        verify_email(app.state(), app.state(), "verify-token".to_string()).await.unwrap();
+        assert!(email_verified(&app, user_id).await);
+        assert!(verify_email(app.state(), app.state(), "verify-token".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_verification_tokens() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        insert_token(&app, "email_verification_tokens", user_id, "verify-token", "-1 minutes").await;
+
+        assert!(verify_email(app.state(), app.state(), "verify-token".to_string()).await.is_err());
+        assert!(!email_verified(&app, user_id).await);
+    }
+
+    #[tokio::test]
+    async fn resending_replaces_the_previous_verification_token() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
+        insert_token(&app, "email_verification_tokens", user_id, "verify-token", "+24 hours").await;
+
+        resend_verification_email(app.state(), app.state(), app.state(), session.access_token.clone()).await.unwrap();
+
+        let rows = sqlx::query("SELECT used, expires_at > datetime('now', '+23 hours') AS fresh FROM email_verification_tokens WHERE user_id = ? ORDER BY id")
+            .bind(user_id)
+            .fetch_all(app.state::<Database>().get_pool())
+            .await
+            .unwrap();
+        let states: Vec<(bool, bool)> = rows.iter().map(|row| (row.get("used"), row.get("fresh"))).collect();
+        assert_eq!(states, [(true, true), (false, true)]);
+        assert!(verify_email(app.state(), app.state(), "verify-token".to_string()).await.is_err());
+
+        // Nothing left to verify once verified
+        sqlx::query("UPDATE users SET email_verified = 1 WHERE id = ?")
+            .bind(user_id)
+            .execute(app.state::<Database>().get_pool())
+            .await
+            .unwrap();
+        assert!(resend_verification_email(app.state(), app.state(), app.state(), session.access_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_logins_are_audited_without_the_email_address() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+
+        let login_data = LoginRequest { email: "jane@example.com".to_string(), password: "not-it".to_string() };
+        assert!(login_user(app.state(), app.state(), login_data, None, None).await.is_err());
+
+        let row = sqlx::query("SELECT actor_user_id, outcome, metadata FROM audit_events WHERE event_type = 'login'")
+            .fetch_one(app.state::<Database>().get_pool())
+            .await
+            .unwrap();
+        let metadata: serde_json::Value = serde_json::from_str(row.get("metadata")).unwrap();
+        assert_eq!(row.get::<Option<i64>, _>("actor_user_id"), Some(user_id));
+        assert_eq!(row.get::<String, _>("outcome"), "failure");
+        assert_eq!(metadata["reason"], "wrong_password");
+        assert_eq!(metadata["email_hash"], audit::email_hash(&app.state(), "Jane@Example.com "));
+        assert!(!row.get::<String, _>("metadata").contains("jane@example.com"));
+    }
+}
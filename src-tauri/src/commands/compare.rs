@@ -1,16 +1,29 @@
 use tauri::State;
 use sqlx::Row;
 use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::mail::{MailQueue, OutgoingMail};
+use crate::preferences;
+use crate::privacy::{Audience, ON_LEADERBOARD_SQL};
+use crate::profile::normalize_handle;
+use crate::session::authenticate;
 
 // Compare commands
 #[tauri::command]
 pub async fn compare_progress(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     friend_ids: Vec<i64>,
     category: Option<String>,
     metric: Option<String>,
 ) -> Result<serde_json::Value, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    // Everyone's values are shown in the caller's unit system
+    let unit_system = preferences::load(&db, user_id).await?.unit_system;
+
     let mut comparison_data = serde_json::Map::new();
 
     // Get user's progress
@@ -39,12 +52,13 @@ pub async fn compare_progress(
         .map_err(|e| e.to_string())?;
     
     let user_progress_json: Vec<serde_json::Value> = user_progress.into_iter().map(|row| {
+        let (value, unit) = unit_system.convert(row.get("value"), row.get::<Option<&str>, _>("unit"));
         serde_json::json!({
             "id": row.get::<i64, _>("id"),
             "category": row.get::<String, _>("category"),
             "metric": row.get::<String, _>("metric"),
-            "value": row.get::<f64, _>("value"),
-            "unit": row.get::<Option<String>, _>("unit"),
+            "value": value,
+            "unit": unit,
             "date": row.get::<String, _>("date"),
             "notes": row.get::<Option<String>, _>("notes")
         })
@@ -54,7 +68,12 @@ pub async fn compare_progress(
 
     // Get friends' progress
     for friend_id in friend_ids {
-        let mut friend_query = "SELECT * FROM progress WHERE user_id = ?".to_string();
+        let audience = Audience::of(&db, friend_id, user_id).await?;
+        let with_notes = audience.sees_notes(&db, friend_id).await?;
+
+        // Deactivated accounts drop out of comparisons
+        let mut friend_query = "SELECT p.* FROM progress p JOIN users u ON u.id = p.user_id WHERE p.user_id = ? AND u.is_active = 1".to_string();
+        friend_query.push_str(&audience.progress_filter());
         
         if category.is_some() {
             friend_query.push_str(" AND category = ?");
@@ -79,14 +98,15 @@ pub async fn compare_progress(
             .map_err(|e| e.to_string())?;
         
         let friend_progress_json: Vec<serde_json::Value> = friend_progress.into_iter().map(|row| {
+            let (value, unit) = unit_system.convert(row.get("value"), row.get::<Option<&str>, _>("unit"));
             serde_json::json!({
                 "id": row.get::<i64, _>("id"),
                 "category": row.get::<String, _>("category"),
                 "metric": row.get::<String, _>("metric"),
-                "value": row.get::<f64, _>("value"),
-                "unit": row.get::<Option<String>, _>("unit"),
+                "value": value,
+                "unit": unit,
                 "date": row.get::<String, _>("date"),
-                "notes": row.get::<Option<String>, _>("notes")
+                "notes": if with_notes { row.get::<Option<String>, _>("notes") } else { None }
             })
         }).collect();
 
@@ -96,24 +116,73 @@ pub async fn compare_progress(
     Ok(serde_json::Value::Object(comparison_data))
 }
 
+/// Invites by handle or by email, exactly one of them. Problems with a
+/// handle are reported; an invite by email always looks like it succeeded,
+/// so invites cannot be used to probe which addresses are registered.
 #[tauri::command]
 pub async fn invite_friend(
     db: State<'_, Database>,
-    user_id: i64,
-    friend_email: String,
+    keys: State<'_, KeyStore>,
+    mail: State<'_, MailQueue>,
+    access_token: String,
+    friend_email: Option<String>,
+    friend_handle: Option<String>,
 ) -> Result<(), String> {
-    // Find friend by email
-    let friend_row = sqlx::query("SELECT id FROM users WHERE email = ?")
-        .bind(&friend_email)
-        .fetch_optional(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    if db.setting_enabled("require_verified_email_for_invites", true).await.map_err(|e| e.to_string())? {
+        let verified_row = sqlx::query("SELECT email_verified FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(db.get_pool())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !verified_row.get::<bool, _>("email_verified") {
+            return Err(AppError::Unauthorized("Verify your email address before inviting friends".to_string()).into());
+        }
+    }
+
+    let friend_row = match (&friend_email, &friend_handle) {
+        (None, Some(handle)) => {
+            let row = sqlx::query("SELECT id, email, first_name FROM users WHERE handle = ? AND is_active = 1")
+                .bind(normalize_handle(handle))
+                .fetch_optional(db.get_pool())
+                .await
+                .map_err(|e| e.to_string())?;
 
-    let friend_id: i64 = match friend_row {
-        Some(row) => row.get::<i64, _>("id"),
-        None => return Err("User not found".to_string()),
+            match row {
+                Some(row) => row,
+                None => return Err(AppError::NotFound("No user with that handle".to_string()).into()),
+            }
+        }
+        (Some(email), None) => {
+            let row = sqlx::query("SELECT id, email, first_name FROM users WHERE email = ? AND is_active = 1")
+                .bind(email.trim())
+                .fetch_optional(db.get_pool())
+                .await
+                .map_err(|e| e.to_string())?;
+
+            match row {
+                Some(row) => row,
+                None => return Ok(()),
+            }
+        }
+        _ => return Err(AppError::Validation("Give either a handle or an email address".to_string()).into()),
     };
 
+    let friend_id: i64 = friend_row.get("id");
+    let friend_email: String = friend_row.get("email");
+    let friend_first_name: String = friend_row.get("first_name");
+
+    let by_email = friend_handle.is_none();
+
+    if friend_id == user_id {
+        if by_email {
+            return Ok(());
+        }
+        return Err(AppError::Validation("You cannot invite yourself".to_string()).into());
+    }
+
     // Check if friendship already exists
     let existing_friendship = sqlx::query("SELECT id FROM user_friends WHERE user_id = ? AND friend_id = ?")
         .bind(user_id)
@@ -123,36 +192,54 @@ pub async fn invite_friend(
         .map_err(|e| e.to_string())?;
 
     if existing_friendship.is_some() {
+        if by_email {
+            return Ok(());
+        }
         return Err("Friendship already exists".to_string());
     }
 
     // Create friendship invitation
     sqlx::query("INSERT INTO user_friends (user_id, friend_id, status) VALUES (?, ?, 'pending')")
-        .bind(&user_id)
-        .bind(&friend_id)
+        .bind(user_id)
+        .bind(friend_id)
         .execute(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
     // Create a notification
     sqlx::query("INSERT INTO notifications (user_id, title, message, type) VALUES (?, ?, ?, 'info')")
-        .bind(&friend_id)
+        .bind(friend_id)
         .bind("New Friend Invitation")
         .bind("You have received a friend invitation to compare progress!")
         .execute(db.get_pool())
         .await
         .map_err(|e| e.to_string())?;
 
+    let inviter_row = sqlx::query("SELECT first_name, last_name FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    let inviter_name = format!("{} {}", inviter_row.get::<String, _>("first_name"), inviter_row.get::<String, _>("last_name"));
+
+    if preferences::load(&db, friend_id).await?.email_notifications {
+        mail.enqueue(OutgoingMail::friend_invite(&friend_email, &friend_first_name, &inviter_name));
+    }
+
     Ok(())
 }
 
 #[tauri::command]
 pub async fn get_leaderboard(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     category: Option<String>,
     metric: Option<String>,
     limit: Option<i64>,
 ) -> Result<Vec<serde_json::Value>, String> {
+    authenticate(&db, &keys, &access_token).await?;
+
     let limit = limit.unwrap_or(10);
 
     let mut query = r#"
@@ -163,6 +250,12 @@ pub async fn get_leaderboard(
         WHERE u.is_active = 1
     "#.to_string();
 
+    // The leaderboard is visible to everyone, so only public categories count
+    query.push_str(&format!(" AND {}{}", ON_LEADERBOARD_SQL, Audience::Anyone.progress_filter()));
+
+    if db.setting_enabled("require_verified_email_for_leaderboard", false).await.map_err(|e| e.to_string())? {
+        query.push_str(" AND u.email_verified = 1");
+    }
     if category.is_some() {
         query.push_str(" AND p.category = ?");
     }
@@ -199,4 +292,68 @@ pub async fn get_leaderboard(
     }).collect();
 
     Ok(leaderboard)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::Manager;
+
+    use crate::test_support;
+
+    async fn invitations(db: &Database, user_id: i64) -> Vec<i64> {
+        sqlx::query("SELECT friend_id FROM user_friends WHERE user_id = ? ORDER BY id")
+            .bind(user_id)
+            .fetch_all(db.get_pool())
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("friend_id"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn invites_by_handle_and_reports_what_went_wrong() {
+        let app = test_support::app().await;
+        let db = app.state::<Database>();
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let friend_id = test_support::create_user(&app, "john@example.com").await;
+        sqlx::query("UPDATE users SET email_verified = 1, handle = 'jane' WHERE id = ?; UPDATE users SET handle = 'john_doe' WHERE id = ?")
+            .bind(user_id)
+            .bind(friend_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        let session = test_support::sign_in(&app, user_id).await;
+
+        let invite = |email: Option<&str>, handle: Option<&str>| invite_friend(
+            app.state(), app.state(), app.state(), session.access_token.clone(), email.map(str::to_string), handle.map(str::to_string),
+        );
+
+        invite(None, Some("@John_Doe")).await.unwrap();
+        assert_eq!(invitations(&db, user_id).await, vec![friend_id]);
+
+        assert!(invite(None, Some("john_doe")).await.is_err());
+        assert!(invite(None, Some("jane")).await.is_err());
+        assert_eq!(invite(None, Some("nobody")).await, Err(AppError::NotFound("No user with that handle".to_string()).into()));
+        assert!(invite(Some("john@example.com"), Some("john_doe")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invites_by_email_do_not_reveal_whether_the_account_exists() {
+        let app = test_support::app().await;
+        let db = app.state::<Database>();
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let friend_id = test_support::create_user(&app, "john@example.com").await;
+        sqlx::query("UPDATE users SET email_verified = 1 WHERE id = ?")
+            .bind(user_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        let session = test_support::sign_in(&app, user_id).await;
+
+        for email in ["john@example.com", "john@example.com", "nobody@example.com", "jane@example.com"] {
+            invite_friend(app.state(), app.state(), app.state(), session.access_token.clone(), Some(email.to_string()), None).await.unwrap();
+        }
+        assert_eq!(invitations(&db, user_id).await, vec![friend_id]);
+    }
+}
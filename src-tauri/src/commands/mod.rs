@@ -3,9 +3,25 @@ pub mod users;
 pub mod progress;
 pub mod compare;
 pub mod other;
+pub mod two_factor;
+pub mod sessions;
+pub mod oidc;
+pub mod audit;
+pub mod roles;
+pub mod passwordless;
+pub mod privacy;
+pub mod preferences;
 
 pub use auth::*;
 pub use users::*;
 pub use progress::*;
 pub use compare::*;
 pub use other::*;
+pub use two_factor::*;
+pub use sessions::*;
+pub use oidc::*;
+pub use audit::*;
+pub use roles::*;
+pub use passwordless::*;
+pub use privacy::*;
+pub use preferences::*;
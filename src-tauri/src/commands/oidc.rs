@@ -0,0 +1,196 @@
+use tauri::State;
+use sqlx::Row;
+use rand::RngCore;
+use serde_json::json;
+
+use crate::audit::{self, AuditEventType, AuditOutcome};
+use crate::commands::two_factor::second_factor_enabled;
+use crate::commands::users::{account_can_sign_in, fetch_user, restore_deactivated_account};
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::models::*;
+use crate::oidc::{IdTokenClaims, OidcClient};
+use crate::password_hashing::PasswordHasher;
+use crate::roles::NEW_ACCOUNT_ROLE_SQL;
+use crate::session::{generate_second_factor_challenge, start_session};
+
+// Single sign-on commands
+#[tauri::command]
+pub async fn begin_oidc_login(
+    oidc: State<'_, OidcClient>,
+) -> Result<OidcAuthorization, String> {
+    Ok(oidc.begin().await?)
+}
+
+/// Finishes a login started with `begin_oidc_login` once the browser has
+/// been redirected back. The identity is matched to an account by issuer
+/// and subject, or linked by verified email on first use; unknown emails
+/// get a new account. Two-factor accounts still get a challenge.
+#[tauri::command]
+pub async fn complete_oidc_login(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    oidc: State<'_, OidcClient>,
+    state: String,
+    client: Option<SessionClient>,
+) -> Result<LoginResponse, String> {
+    let claims = match oidc.complete(&state).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            audit::record(&db, None, AuditEventType::Login, AuditOutcome::Failure, json!({
+                "method": "oidc",
+                "reason": e.to_string(),
+            })).await?;
+            return Err(e.into());
+        }
+    };
+
+    let user_id = match resolve_identity(&db, &claims).await {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            audit::record(&db, None, AuditEventType::Login, AuditOutcome::Failure, json!({
+                "method": "oidc",
+                "issuer": claims.iss,
+                "reason": e.to_string(),
+            })).await?;
+            return Err(e.into());
+        }
+    };
+
+    sqlx::query("UPDATE user_identities SET last_login_at = datetime('now'), email = ? WHERE issuer = ? AND subject = ?")
+        .bind(&claims.email)
+        .bind(&claims.iss)
+        .bind(&claims.sub)
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if second_factor_enabled(&db, user_id).await? {
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Success, json!({
+            "method": "oidc",
+            "issuer": claims.iss,
+            "second_factor_required": true,
+        })).await?;
+        return Ok(LoginResponse::SecondFactorRequired {
+            challenge_token: generate_second_factor_challenge(&keys, user_id)?,
+        });
+    }
+
+    // Deactivated accounts are restored only after every factor passed
+    restore_deactivated_account(&db, user_id).await?;
+
+    let session = start_session(&db, &keys, user_id, &client.unwrap_or_default()).await?;
+
+    audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Success, json!({
+        "method": "oidc",
+        "issuer": claims.iss,
+        "session_id": session.session_id,
+    })).await?;
+
+    Ok(LoginResponse::Authenticated(Box::new(AuthResponse {
+        access_token: session.access_token,
+        refresh_token: session.refresh_token,
+        user: fetch_user(&db, user_id).await?,
+    })))
+}
+
+// Helper functions
+async fn resolve_identity(db: &Database, claims: &IdTokenClaims) -> Result<i64, AppError> {
+    let linked = sqlx::query(
+        "SELECT u.id FROM user_identities i JOIN users u ON u.id = i.user_id WHERE i.issuer = ? AND i.subject = ?"
+    )
+    .bind(&claims.iss)
+    .bind(&claims.sub)
+    .fetch_optional(db.get_pool())
+    .await?;
+
+    if let Some(row) = linked {
+        let user_id: i64 = row.get("id");
+        if !account_can_sign_in(db, user_id).await? {
+            return Err(AppError::Unauthorized("This account is deactivated".to_string()));
+        }
+        return Ok(user_id);
+    }
+
+    // Linking by email is only safe when the provider vouches for it
+    let email = match &claims.email {
+        Some(email) if claims.email_verified() => email.trim().to_string(),
+        _ => return Err(AppError::Unauthorized("Identity provider did not return a verified email address".to_string())),
+    };
+
+    let existing = sqlx::query("SELECT id FROM users WHERE lower(email) = lower(?)")
+        .bind(&email)
+        .fetch_optional(db.get_pool())
+        .await?;
+
+    if let Some(row) = &existing {
+        if !account_can_sign_in(db, row.get("id")).await? {
+            return Err(AppError::Unauthorized("This account is deactivated".to_string()));
+        }
+    }
+
+    let mut tx = db.get_pool().begin().await?;
+
+    let user_id = match existing {
+        Some(row) => {
+            let user_id: i64 = row.get("id");
+            sqlx::query("UPDATE users SET email_verified = 1, updated_at = datetime('now') WHERE id = ?")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            user_id
+        }
+        None => {
+            let (first_name, last_name) = display_name(claims, &email);
+
+            // The account has no usable password until the user sets one
+            // through forgot_password
+            let mut unusable_password = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut unusable_password);
+            let password_hash = PasswordHasher::load(db).await?.hash(&hex::encode(unusable_password))?;
+
+            let insert_query = format!(
+                "INSERT INTO users (email, password_hash, first_name, last_name, goals, email_verified, role) VALUES (?, ?, ?, ?, '[]', 1, {})",
+                NEW_ACCOUNT_ROLE_SQL
+            );
+            sqlx::query(&insert_query)
+                .bind(&email)
+                .bind(&password_hash)
+                .bind(&first_name)
+                .bind(&last_name)
+                .execute(&mut *tx)
+                .await?
+                .last_insert_rowid()
+        }
+    };
+
+    sqlx::query("INSERT INTO user_identities (user_id, issuer, subject, email) VALUES (?, ?, ?, ?)")
+        .bind(user_id)
+        .bind(&claims.iss)
+        .bind(&claims.sub)
+        .bind(&email)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(user_id)
+}
+
+/// First and last name from the standard profile claims, falling back to
+/// the email's local part.
+fn display_name(claims: &IdTokenClaims, email: &str) -> (String, String) {
+    if let Some(given_name) = claims.given_name.as_deref().filter(|n| !n.trim().is_empty()) {
+        return (given_name.trim().to_string(), claims.family_name.clone().unwrap_or_default().trim().to_string());
+    }
+
+    if let Some(name) = claims.name.as_deref().filter(|n| !n.trim().is_empty()) {
+        let mut parts = name.trim().splitn(2, ' ');
+        let first = parts.next().unwrap_or_default().to_string();
+        let last = parts.next().unwrap_or_default().trim().to_string();
+        return (first, last);
+    }
+
+    (email.split('@').next().unwrap_or_default().to_string(), String::new())
+}
@@ -1,16 +1,24 @@
 use tauri::State;
 use sqlx::Row;
+use crate::avatars::AvatarStore;
 use crate::database::Database;
+use crate::keys::KeyStore;
+use crate::maintenance;
+use crate::roles::{authorize, Permission};
+use crate::session::authenticate;
 use crate::models::*;
 
 // Notification commands
 #[tauri::command]
 pub async fn get_notifications(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     limit: Option<i64>,
     offset: Option<i64>,
 ) -> Result<Vec<Notification>, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     let limit = limit.unwrap_or(50);
     let offset = offset.unwrap_or(0);
 
@@ -38,8 +46,12 @@ pub async fn get_notifications(
 #[tauri::command]
 pub async fn create_notification(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     notification_data: NotificationCreate,
 ) -> Result<Notification, String> {
+    authorize(&db, &keys, &access_token, Permission::SendNotifications).await?;
+
     let result = sqlx::query(
         "INSERT INTO notifications (user_id, title, message, type) VALUES (?, ?, ?, ?)"
     )
@@ -73,9 +85,12 @@ pub async fn create_notification(
 #[tauri::command]
 pub async fn mark_notification_read(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     notification_id: i64,
-    user_id: i64,
+    access_token: String,
 ) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     // Verify ownership
     let ownership_check = sqlx::query("SELECT id FROM notifications WHERE id = ? AND user_id = ?")
         .bind(notification_id)
@@ -100,9 +115,12 @@ pub async fn mark_notification_read(
 #[tauri::command]
 pub async fn delete_notification(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     notification_id: i64,
-    user_id: i64,
+    access_token: String,
 ) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     // Verify ownership
     let ownership_check = sqlx::query("SELECT id FROM notifications WHERE id = ? AND user_id = ?")
         .bind(notification_id)
@@ -128,9 +146,12 @@ pub async fn delete_notification(
 #[tauri::command]
 pub async fn create_checkout_session(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     plan_type: String,
 ) -> Result<String, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     // TODO: Integrate with Stripe
     // For now, return a mock session ID
     let session_id = format!("cs_test_{}", uuid::Uuid::new_v4());
@@ -149,8 +170,11 @@ pub async fn create_checkout_session(
 #[tauri::command]
 pub async fn get_subscription(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
 ) -> Result<Option<Subscription>, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     let subscription_row = sqlx::query("SELECT * FROM subscriptions WHERE user_id = ? ORDER BY created_at DESC LIMIT 1")
         .bind(user_id)
         .fetch_optional(db.get_pool())
@@ -177,8 +201,11 @@ pub async fn get_subscription(
 #[tauri::command]
 pub async fn cancel_subscription(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
 ) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     sqlx::query("UPDATE subscriptions SET status = 'canceled', updated_at = datetime('now') WHERE user_id = ? AND status = 'active'")
         .bind(user_id)
         .execute(db.get_pool())
@@ -223,8 +250,12 @@ pub async fn get_settings(
 #[tauri::command]
 pub async fn update_settings(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     settings: Vec<(String, String)>,
 ) -> Result<(), String> {
+    authorize(&db, &keys, &access_token, Permission::ManageSettings).await?;
+
     for (key, value) in settings {
         sqlx::query("UPDATE settings SET value = ?, updated_at = datetime('now') WHERE key = ?")
             .bind(&value)
@@ -240,7 +271,11 @@ pub async fn update_settings(
 #[tauri::command]
 pub async fn get_metrics(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
 ) -> Result<Metrics, String> {
+    authorize(&db, &keys, &access_token, Permission::ViewMetrics).await?;
+
     // Get total users
     let total_users_row = sqlx::query("SELECT COUNT(*) as count FROM users WHERE is_active = 1")
         .fetch_one(db.get_pool())
@@ -283,4 +318,17 @@ pub async fn get_metrics(
         most_popular_category,
         most_popular_metric,
     })
-}
\ No newline at end of file
+}
+
+/// Runs the background maintenance sweep immediately and reports what it removed.
+#[tauri::command]
+pub async fn run_maintenance(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    avatars: State<'_, AvatarStore>,
+    access_token: String,
+) -> Result<SweepReport, String> {
+    authorize(&db, &keys, &access_token, Permission::ManageSettings).await?;
+
+    Ok(maintenance::sweep(&db, &keys, &avatars).await?)
+}
@@ -0,0 +1,232 @@
+use tauri::State;
+use sqlx::Row;
+use rand::Rng;
+use serde_json::json;
+use subtle::ConstantTimeEq;
+
+use crate::audit::{self, AuditEventType, AuditOutcome};
+use crate::commands::two_factor::second_factor_enabled;
+use crate::commands::users::fetch_user;
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::mail::{MailQueue, OutgoingMail};
+use crate::models::*;
+use crate::session::{generate_second_factor_challenge, start_session};
+use crate::throttle::LoginThrottle;
+
+// Passwordless commands
+/// Emails a short-lived single-use sign-in code. Succeeds whether or not the
+/// address belongs to an account, so it cannot be used to probe for users.
+/// Every request counts against the login throttle, per email and client,
+/// which bounds both mailbox flooding and fresh guessing windows.
+#[tauri::command]
+pub async fn request_login_code(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    mail: State<'_, MailQueue>,
+    email: String,
+    client_id: Option<String>,
+) -> Result<(), String> {
+    if !db.setting_enabled("passwordless_login_enabled", true).await.map_err(|e| e.to_string())? {
+        return Err(AppError::Unauthorized("Sign-in codes are disabled".to_string()).into());
+    }
+
+    let throttle = LoginThrottle::load(&db).await.map_err(|e| e.to_string())?;
+    let throttle_keys: Vec<String> = throttle_keys(&email, client_id.as_deref()).iter()
+        .map(|key| LoginThrottle::code_request_key(key))
+        .collect();
+    for key in &throttle_keys {
+        if let Err(e) = throttle.check(&db, key).await {
+            audit::record(&db, None, AuditEventType::LoginCodeRequested, AuditOutcome::Failure, json!({
+                "email_hash": audit::email_hash(&keys, &email),
+                "client_id": client_id,
+                "reason": "throttled",
+            })).await?;
+            return Err(e.into());
+        }
+    }
+    for key in &throttle_keys {
+        throttle.record_failure(&db, key).await?;
+    }
+
+    let user_row = sqlx::query("SELECT id, first_name FROM users WHERE email = ? AND is_active = 1")
+        .bind(&email)
+        .fetch_optional(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let user_row = match user_row {
+        Some(row) => row,
+        None => return Ok(()),
+    };
+    let user_id: i64 = user_row.get("id");
+
+    let ttl_minutes: i64 = db.get_setting("login_code_ttl_minutes").await
+        .map_err(|e| e.to_string())?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10);
+
+    // Only the most recent code stays valid
+    sqlx::query("UPDATE login_codes SET used_at = datetime('now') WHERE user_id = ? AND used_at IS NULL")
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+
+    sqlx::query("INSERT INTO login_codes (user_id, code_hash, expires_at) VALUES (?, ?, datetime('now', ?))")
+        .bind(user_id)
+        .bind(keys.hash_token(&code))
+        .bind(format!("+{} minutes", ttl_minutes))
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    audit::record(&db, Some(user_id), AuditEventType::LoginCodeRequested, AuditOutcome::Success, json!({})).await?;
+
+    mail.enqueue(OutgoingMail::login_code(&email, &user_row.get::<String, _>("first_name"), &code, ttl_minutes));
+
+    Ok(())
+}
+
+/// Exchanges a code from `request_login_code` for a session, exactly like a
+/// successful `login_user`. Each wrong guess counts against the code; once
+/// `login_code_max_attempts` is reached the code is burned. Wrong guesses
+/// also count against the same login throttle as wrong passwords.
+#[tauri::command]
+pub async fn login_with_code(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    email: String,
+    code: String,
+    client_id: Option<String>,
+    client: Option<SessionClient>,
+) -> Result<LoginResponse, String> {
+    let throttle = LoginThrottle::load(&db).await.map_err(|e| e.to_string())?;
+    let throttle_keys = throttle_keys(&email, client_id.as_deref());
+    for key in &throttle_keys {
+        if let Err(e) = throttle.check(&db, key).await {
+            audit::record(&db, None, AuditEventType::Login, AuditOutcome::Failure, json!({
+                "email_hash": audit::email_hash(&keys, &email),
+                "client_id": client_id,
+                "method": "code",
+                "reason": "throttled",
+            })).await?;
+            return Err(e.into());
+        }
+    }
+
+    let max_attempts: i64 = db.get_setting("login_code_max_attempts").await
+        .map_err(|e| e.to_string())?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5);
+
+    let code_row = sqlx::query(
+        r#"
+        SELECT c.id, c.user_id, c.code_hash, c.attempts FROM login_codes c
+        JOIN users u ON u.id = c.user_id
+        WHERE u.email = ? AND u.is_active = 1 AND c.used_at IS NULL AND c.expires_at > datetime('now')
+        ORDER BY c.created_at DESC, c.id DESC LIMIT 1
+        "#
+    )
+    .bind(&email)
+    .fetch_optional(db.get_pool())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let code_row = match code_row {
+        Some(row) => row,
+        None => {
+            for key in &throttle_keys {
+                throttle.record_failure(&db, key).await?;
+            }
+            audit::record(&db, None, AuditEventType::Login, AuditOutcome::Failure, json!({
+                "email_hash": audit::email_hash(&keys, &email),
+                "method": "code",
+                "reason": "no_active_code",
+            })).await?;
+            return Err("Invalid or expired sign-in code".to_string());
+        }
+    };
+
+    let code_id: i64 = code_row.get("id");
+    let user_id: i64 = code_row.get("user_id");
+
+    let code_hash = keys.hash_token(code.trim());
+    if !bool::from(code_row.get::<&str, _>("code_hash").as_bytes().ct_eq(code_hash.as_bytes())) {
+        for key in &throttle_keys {
+            throttle.record_failure(&db, key).await?;
+        }
+
+        // The attempt is counted, and the code burned at the limit, in one statement
+        sqlx::query("UPDATE login_codes SET attempts = attempts + 1, used_at = CASE WHEN attempts + 1 >= ? THEN datetime('now') ELSE used_at END WHERE id = ?")
+            .bind(max_attempts)
+            .bind(code_id)
+            .execute(db.get_pool())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Failure, json!({
+            "method": "code",
+            "reason": "wrong_code",
+            "attempts": code_row.get::<i64, _>("attempts") + 1,
+        })).await?;
+
+        return Err("Invalid or expired sign-in code".to_string());
+    }
+
+    // Claim the code; a concurrent exchange of the same code loses here
+    let claimed = sqlx::query("UPDATE login_codes SET used_at = datetime('now') WHERE id = ? AND used_at IS NULL")
+        .bind(code_id)
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if claimed.rows_affected() == 0 {
+        return Err("Invalid or expired sign-in code".to_string());
+    }
+
+    throttle.record_success(&db, &throttle_keys[0]).await?;
+    throttle.record_success(&db, &LoginThrottle::code_request_key(&throttle_keys[0])).await?;
+
+    // Receiving the code proves control of the mailbox
+    sqlx::query("UPDATE users SET email_verified = 1 WHERE id = ? AND email_verified = 0")
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if second_factor_enabled(&db, user_id).await? {
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Success, json!({
+            "method": "code",
+            "second_factor_required": true,
+        })).await?;
+        return Ok(LoginResponse::SecondFactorRequired {
+            challenge_token: generate_second_factor_challenge(&keys, user_id)?,
+        });
+    }
+
+    let session = start_session(&db, &keys, user_id, &client.unwrap_or_default()).await?;
+
+    audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Success, json!({
+        "method": "code",
+        "session_id": session.session_id,
+    })).await?;
+
+    Ok(LoginResponse::Authenticated(Box::new(AuthResponse {
+        access_token: session.access_token,
+        refresh_token: session.refresh_token,
+        user: fetch_user(&db, user_id).await?,
+    })))
+}
+
+// Email first, then the client when one identifies itself
+fn throttle_keys(email: &str, client_id: Option<&str>) -> Vec<String> {
+    let mut keys = vec![LoginThrottle::email_key(email)];
+    if let Some(client_id) = client_id.filter(|id| !id.is_empty()) {
+        keys.push(LoginThrottle::client_key(client_id));
+    }
+    keys
+}
@@ -0,0 +1,33 @@
+use tauri::State;
+
+use crate::database::Database;
+use crate::keys::KeyStore;
+use crate::models::*;
+use crate::preferences;
+use crate::session::authenticate;
+
+// Preference commands
+#[tauri::command]
+pub async fn get_preferences(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+) -> Result<UserPreferences, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    Ok(preferences::load(&db, user_id).await?)
+}
+
+#[tauri::command]
+pub async fn update_preferences(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    update_data: UserPreferencesUpdate,
+) -> Result<UserPreferences, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    preferences::update(&db, user_id, &update_data).await?;
+
+    Ok(preferences::load(&db, user_id).await?)
+}
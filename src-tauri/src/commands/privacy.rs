@@ -0,0 +1,44 @@
+use tauri::State;
+use serde_json::json;
+
+use crate::audit::{self, AuditEventType, AuditOutcome};
+use crate::database::Database;
+use crate::keys::KeyStore;
+use crate::models::*;
+use crate::privacy;
+use crate::session::authenticate;
+
+// Privacy commands
+#[tauri::command]
+pub async fn get_privacy_settings(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+) -> Result<PrivacySettings, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    Ok(privacy::load(&db, user_id).await?)
+}
+
+#[tauri::command]
+pub async fn update_privacy_settings(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    update_data: PrivacySettingsUpdate,
+) -> Result<PrivacySettings, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    privacy::update(&db, user_id, &update_data).await?;
+
+    let settings = privacy::load(&db, user_id).await?;
+    audit::record(&db, Some(user_id), AuditEventType::PrivacySettingsUpdated, AuditOutcome::Success, json!({
+        "default_visibility": settings.default_visibility,
+        "share_notes": settings.share_notes,
+        "show_on_leaderboard": settings.show_on_leaderboard,
+        "discoverable": settings.discoverable,
+        "category_overrides": settings.categories.len(),
+    })).await?;
+
+    Ok(settings)
+}
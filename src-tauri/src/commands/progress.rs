@@ -1,15 +1,30 @@
+use std::collections::BTreeMap;
+
 use tauri::State;
 use sqlx::Row;
+use chrono::NaiveDate;
 use crate::database::Database;
+use crate::keys::KeyStore;
+use crate::preferences::{self, SummaryPeriod};
+use crate::privacy::Audience;
+use crate::session::authenticate;
 use crate::models::*;
 
+const MAX_SUMMARY_PERIODS: u32 = 366;
+
+// Period start, category, metric and unit
+type BucketKey = (NaiveDate, String, String, Option<String>);
+
 // Progress commands
 #[tauri::command]
 pub async fn add_progress(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     progress_data: ProgressCreate,
 ) -> Result<Progress, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     let result = sqlx::query(
         "INSERT INTO progress (user_id, category, metric, value, unit, notes, date) VALUES (?, ?, ?, ?, ?, ?, ?)"
     )
@@ -49,11 +64,17 @@ pub async fn add_progress(
 #[tauri::command]
 pub async fn get_user_progress(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     limit: Option<i64>,
     offset: Option<i64>,
 ) -> Result<Vec<Progress>, String> {
-    let limit = limit.unwrap_or(100);
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let limit = match limit {
+        Some(limit) => limit,
+        None => preferences::load(&db, user_id).await?.progress_page_size,
+    };
     let offset = offset.unwrap_or(0);
 
     let rows = sqlx::query("SELECT * FROM progress WHERE user_id = ? ORDER BY date DESC, created_at DESC LIMIT ? OFFSET ?")
@@ -83,14 +104,34 @@ pub async fn get_user_progress(
 #[tauri::command]
 pub async fn get_user_progress_by_id(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
     target_user_id: i64,
     limit: Option<i64>,
     offset: Option<i64>,
 ) -> Result<Vec<Progress>, String> {
-    let limit = limit.unwrap_or(100);
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let limit = match limit {
+        Some(limit) => limit,
+        None => preferences::load(&db, user_id).await?.progress_page_size,
+    };
     let offset = offset.unwrap_or(0);
 
-    let rows = sqlx::query("SELECT * FROM progress WHERE user_id = ? ORDER BY date DESC, created_at DESC LIMIT ? OFFSET ?")
+    let audience = Audience::of(&db, target_user_id, user_id).await?;
+    let with_notes = audience.sees_notes(&db, target_user_id).await?;
+
+    let query = format!(
+        r#"
+        SELECT p.* FROM progress p
+        JOIN users u ON u.id = p.user_id
+        WHERE p.user_id = ? AND u.is_active = 1{}
+        ORDER BY p.date DESC, p.created_at DESC LIMIT ? OFFSET ?
+        "#,
+        audience.progress_filter()
+    );
+
+    let rows = sqlx::query(&query)
         .bind(target_user_id)
         .bind(limit)
         .bind(offset)
@@ -105,7 +146,7 @@ pub async fn get_user_progress_by_id(
         metric: row.get("metric"),
         value: row.get("value"),
         unit: row.get("unit"),
-        notes: row.get("notes"),
+        notes: if with_notes { row.get("notes") } else { None },
         date: row.get("date"),
         created_at: row.get("created_at"),
         updated_at: row.get("updated_at"),
@@ -117,10 +158,13 @@ pub async fn get_user_progress_by_id(
 #[tauri::command]
 pub async fn update_progress(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     progress_id: i64,
-    user_id: i64,
+    access_token: String,
     update_data: ProgressUpdate,
 ) -> Result<Progress, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     // Verify ownership
     let ownership_check = sqlx::query("SELECT id FROM progress WHERE id = ? AND user_id = ?")
         .bind(progress_id)
@@ -229,9 +273,12 @@ pub async fn update_progress(
 #[tauri::command]
 pub async fn delete_progress(
     db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
     progress_id: i64,
-    user_id: i64,
+    access_token: String,
 ) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
     // Verify ownership
     let ownership_check = sqlx::query("SELECT id FROM progress WHERE id = ? AND user_id = ?")
         .bind(progress_id)
@@ -251,4 +298,72 @@ pub async fn delete_progress(
         .map_err(|e| e.to_string())?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Totals and averages of the caller's progress over the last `periods`
+/// days, weeks or months, bucketed with their week start and time zone and
+/// converted to their unit system.
+#[tauri::command]
+pub async fn get_progress_summary(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    period: Option<SummaryPeriod>,
+    periods: Option<u32>,
+    category: Option<String>,
+    metric: Option<String>,
+) -> Result<Vec<ProgressBucket>, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let prefs = preferences::load(&db, user_id).await?;
+    let period = period.unwrap_or(SummaryPeriod::Week);
+    let periods = periods.unwrap_or(12).clamp(1, MAX_SUMMARY_PERIODS);
+
+    let mut query = "SELECT category, metric, value, unit, date FROM progress WHERE user_id = ? AND date >= ? AND date <= ?".to_string();
+    if category.is_some() {
+        query.push_str(" AND category = ?");
+    }
+    if metric.is_some() {
+        query.push_str(" AND metric = ?");
+    }
+
+    let mut query_builder = sqlx::query(&query)
+        .bind(user_id)
+        .bind(prefs.window_start(period, periods))
+        .bind(prefs.today());
+    if let Some(cat) = &category {
+        query_builder = query_builder.bind(cat);
+    }
+    if let Some(met) = &metric {
+        query_builder = query_builder.bind(met);
+    }
+
+    let rows = query_builder
+        .fetch_all(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut buckets: BTreeMap<BucketKey, (i64, f64)> = BTreeMap::new();
+    for row in rows {
+        let (value, unit) = prefs.unit_system.convert(row.get("value"), row.get::<Option<&str>, _>("unit"));
+        let key = (
+            prefs.period_start(period, row.get("date")),
+            row.get("category"),
+            row.get("metric"),
+            unit,
+        );
+        let bucket = buckets.entry(key).or_default();
+        bucket.0 += 1;
+        bucket.1 += value;
+    }
+
+    Ok(buckets.into_iter().map(|((period_start, category, metric, unit), (entries, total))| ProgressBucket {
+        period_start,
+        category,
+        metric,
+        unit,
+        entries,
+        total,
+        average: total / entries as f64,
+    }).collect())
+}
@@ -0,0 +1,80 @@
+use tauri::State;
+use sqlx::Row;
+use serde_json::json;
+
+use crate::audit::{self, AuditEventType, AuditOutcome};
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::roles::{authorize, role_of, Permission, Role};
+
+// Role commands
+#[tauri::command]
+pub async fn grant_role(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    user_id: i64,
+    role: Role,
+) -> Result<(), String> {
+    let admin_id = authorize(&db, &keys, &access_token, Permission::ManageRoles).await?.user_id;
+
+    set_role(&db, admin_id, user_id, role).await?;
+
+    audit::record(&db, Some(user_id), AuditEventType::RoleGranted, AuditOutcome::Success, json!({
+        "role": role.as_str(),
+        "granted_by": admin_id,
+    })).await?;
+
+    Ok(())
+}
+
+/// Returns the account to the plain `user` role.
+#[tauri::command]
+pub async fn revoke_role(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    user_id: i64,
+) -> Result<(), String> {
+    let admin_id = authorize(&db, &keys, &access_token, Permission::ManageRoles).await?.user_id;
+
+    let previous = set_role(&db, admin_id, user_id, Role::User).await?;
+
+    audit::record(&db, Some(user_id), AuditEventType::RoleRevoked, AuditOutcome::Success, json!({
+        "role": previous.as_str(),
+        "revoked_by": admin_id,
+    })).await?;
+
+    Ok(())
+}
+
+// Helper functions
+/// Returns the role the account had before.
+async fn set_role(db: &Database, admin_id: i64, user_id: i64, role: Role) -> Result<Role, AppError> {
+    let previous = role_of(db, user_id).await?;
+
+    // Never leave the install without an active admin
+    if previous == Role::Admin && role != Role::Admin {
+        let admins = sqlx::query("SELECT COUNT(*) as count FROM users WHERE role = 'admin' AND is_active = 1")
+            .fetch_one(db.get_pool())
+            .await?;
+
+        if admins.get::<i64, _>("count") <= 1 {
+            let message = if user_id == admin_id {
+                "You are the only admin; grant the admin role to someone else first"
+            } else {
+                "Cannot remove the last admin"
+            };
+            return Err(AppError::Conflict(message.to_string()));
+        }
+    }
+
+    sqlx::query("UPDATE users SET role = ?, updated_at = datetime('now') WHERE id = ?")
+        .bind(role.as_str())
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await?;
+
+    Ok(previous)
+}
@@ -0,0 +1,137 @@
+use tauri::State;
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::models::*;
+use crate::session::{authenticate, revoke_all_sessions, revoke_session};
+
+// Session commands
+#[tauri::command]
+pub async fn list_sessions(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+) -> Result<Vec<ActiveSession>, String> {
+    let auth = authenticate(&db, &keys, &access_token).await?;
+
+    // Same liveness rule as `authenticate`: unrevoked with an unexpired current token
+    let rows = sqlx::query(
+        r#"
+        SELECT f.id, f.device_label, f.client_info, f.created_at, f.last_used_at
+        FROM refresh_token_families f
+        WHERE f.user_id = ? AND f.revoked_at IS NULL
+          AND EXISTS (
+              SELECT 1 FROM refresh_tokens rt
+              WHERE rt.family_id = f.id AND rt.rotated_at IS NULL AND rt.expires_at > datetime('now')
+          )
+        ORDER BY COALESCE(f.last_used_at, f.created_at) DESC
+        "#
+    )
+    .bind(auth.user_id)
+    .fetch_all(db.get_pool())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let sessions = rows.into_iter().map(|row| {
+        let id: i64 = row.get("id");
+        ActiveSession {
+            id,
+            device_label: row.get("device_label"),
+            client_info: row.get("client_info"),
+            created_at: row.get("created_at"),
+            last_used_at: row.get("last_used_at"),
+            is_current: id == auth.session_id,
+        }
+    }).collect();
+
+    Ok(sessions)
+}
+
+/// Signs out one of the caller's sessions, e.g. a lost device. Revoking the
+/// current session works too and behaves like `logout_user`.
+#[tauri::command]
+pub async fn revoke_user_session(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    session_id: i64,
+) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    if !revoke_session(&db, user_id, session_id, "revoked_by_user").await? {
+        return Err(AppError::NotFound("Session not found".to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// Signs out every session except the one making the call. Returns how many
+/// sessions were signed out.
+#[tauri::command]
+pub async fn revoke_other_sessions(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+) -> Result<u64, String> {
+    let auth = authenticate(&db, &keys, &access_token).await?;
+
+    Ok(revoke_all_sessions(&db, auth.user_id, Some(auth.session_id), "revoked_by_user").await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::Manager;
+
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn lists_only_the_callers_live_sessions() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let other_id = test_support::create_user(&app, "john@example.com").await;
+        let current = test_support::sign_in(&app, user_id).await;
+        let laptop = test_support::sign_in(&app, user_id).await;
+        let revoked = test_support::sign_in(&app, user_id).await;
+        test_support::sign_in(&app, other_id).await;
+
+        revoke_user_session(app.state(), app.state(), current.access_token.clone(), revoked.session_id).await.unwrap();
+
+        let sessions = list_sessions(app.state(), app.state(), current.access_token).await.unwrap();
+        let mut ids: Vec<(i64, bool)> = sessions.iter().map(|session| (session.id, session.is_current)).collect();
+        ids.sort();
+        assert_eq!(ids, vec![(current.session_id, true), (laptop.session_id, false)]);
+    }
+
+    #[tokio::test]
+    async fn cannot_revoke_another_users_session() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let other_id = test_support::create_user(&app, "john@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
+        let other = test_support::sign_in(&app, other_id).await;
+
+        let result = revoke_user_session(app.state(), app.state(), session.access_token, other.session_id).await;
+        assert_eq!(result, Err(AppError::NotFound("Session not found".to_string()).into()));
+
+        assert_eq!(list_sessions(app.state(), app.state(), other.access_token).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn revoking_other_sessions_keeps_the_calling_one() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let current = test_support::sign_in(&app, user_id).await;
+        let phone = test_support::sign_in(&app, user_id).await;
+        test_support::sign_in(&app, user_id).await;
+
+        assert_eq!(revoke_other_sessions(app.state(), app.state(), current.access_token.clone()).await.unwrap(), 2);
+
+        assert!(list_sessions(app.state(), app.state(), phone.access_token).await.is_err());
+        let sessions = list_sessions(app.state(), app.state(), current.access_token).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].is_current);
+    }
+}
@@ -0,0 +1,291 @@
+use tauri::State;
+use sqlx::Row;
+use serde_json::json;
+
+use crate::audit::{self, AuditEventType, AuditOutcome};
+use crate::commands::users::{account_can_sign_in, fetch_user, restore_deactivated_account};
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::models::*;
+use crate::session::{authenticate, start_session, verify_token, TokenType};
+use crate::throttle::LoginThrottle;
+use crate::totp;
+
+// Two-factor commands
+#[tauri::command]
+pub async fn enroll_totp(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+) -> Result<TotpEnrollment, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    if second_factor_enabled(&db, user_id).await? {
+        return Err(AppError::Conflict("Two-factor authentication is already enabled".to_string()).into());
+    }
+
+    let user_row = sqlx::query("SELECT email FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Starting over replaces any enrollment that was never confirmed
+    let secret = totp::generate_secret();
+    let encrypted_secret = keys.encrypt_secret(&secret)?;
+    sqlx::query(
+        r#"
+        INSERT INTO user_totp (user_id, secret) VALUES (?, ?)
+        ON CONFLICT(user_id) DO UPDATE SET secret = excluded.secret, confirmed_at = NULL, last_used_step = NULL, created_at = datetime('now')
+        "#
+    )
+    .bind(user_id)
+    .bind(&encrypted_secret)
+    .execute(db.get_pool())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(TotpEnrollment {
+        otpauth_uri: totp::otpauth_uri(&secret, &user_row.get::<String, _>("email")),
+        secret,
+    })
+}
+
+/// Activates a pending enrollment once the user proves their app produces
+/// valid codes, and returns the recovery codes. They are only shown here.
+#[tauri::command]
+pub async fn confirm_totp(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    code: String,
+) -> Result<Vec<String>, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let totp_row = sqlx::query("SELECT secret FROM user_totp WHERE user_id = ? AND confirmed_at IS NULL")
+        .bind(user_id)
+        .fetch_optional(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let secret = match totp_row {
+        Some(row) => keys.decrypt_secret(row.get("secret"))?,
+        None => return Err(AppError::NotFound("No pending two-factor enrollment".to_string()).into()),
+    };
+
+    let step = match totp::verify_code(&secret, &code, None) {
+        Some(step) => step,
+        None => return Err(AppError::Validation("Invalid authentication code".to_string()).into()),
+    };
+
+    let recovery_codes = totp::generate_recovery_codes();
+
+    let mut tx = db.get_pool().begin().await.map_err(|e| e.to_string())?;
+
+    sqlx::query("UPDATE user_totp SET confirmed_at = datetime('now'), last_used_step = ? WHERE user_id = ?")
+        .bind(step)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for recovery_code in &recovery_codes {
+        sqlx::query("INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(keys.hash_token(&totp::normalize_recovery_code(recovery_code)))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(recovery_codes)
+}
+
+#[tauri::command]
+pub async fn disable_totp(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    code: String,
+) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    if !check_second_factor(&db, &keys, user_id, &code).await? {
+        return Err(AppError::Unauthorized("Invalid authentication code".to_string()).into());
+    }
+
+    sqlx::query("DELETE FROM user_totp WHERE user_id = ?")
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Second step of `login_user` for accounts with two-factor authentication.
+/// Accepts either a current authenticator code or an unused recovery code.
+#[tauri::command]
+pub async fn verify_second_factor(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    challenge_token: String,
+    code: String,
+    client: Option<SessionClient>,
+) -> Result<AuthResponse, String> {
+    let user_id = match verify_token(&keys, &challenge_token, TokenType::SecondFactor).and_then(|claims| claims.user_id()) {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            audit::record(&db, None, AuditEventType::Login, AuditOutcome::Failure, json!({
+                "method": "second_factor",
+                "reason": "invalid_challenge",
+            })).await?;
+            return Err(e.into());
+        }
+    };
+
+    if !account_can_sign_in(&db, user_id).await? {
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Failure, json!({
+            "method": "second_factor",
+            "reason": "account_inactive",
+        })).await?;
+        return Err(AppError::Unauthorized("This account is deactivated".to_string()).into());
+    }
+
+    // Six digits are easy to guess without the same throttling as passwords
+    let throttle = LoginThrottle::load(&db).await.map_err(|e| e.to_string())?;
+    let throttle_key = format!("second_factor:{}", user_id);
+    if let Err(e) = throttle.check(&db, &throttle_key).await {
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Failure, json!({
+            "method": "second_factor",
+            "reason": "throttled",
+        })).await?;
+        return Err(e.into());
+    }
+
+    if !check_second_factor(&db, &keys, user_id, &code).await? {
+        throttle.record_failure(&db, &throttle_key).await?;
+        audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Failure, json!({
+            "method": "second_factor",
+            "reason": "wrong_code",
+        })).await?;
+        return Err(AppError::Unauthorized("Invalid authentication code".to_string()).into());
+    }
+
+    throttle.record_success(&db, &throttle_key).await?;
+
+    // A deactivated account is restored only now that both factors passed
+    restore_deactivated_account(&db, user_id).await?;
+
+    let session = start_session(&db, &keys, user_id, &client.unwrap_or_default()).await?;
+
+    audit::record(&db, Some(user_id), AuditEventType::Login, AuditOutcome::Success, json!({
+        "method": "second_factor",
+        "session_id": session.session_id,
+    })).await?;
+
+    Ok(AuthResponse {
+        access_token: session.access_token,
+        refresh_token: session.refresh_token,
+        user: fetch_user(&db, user_id).await?,
+    })
+}
+
+// Helper functions
+pub(crate) async fn second_factor_enabled(db: &Database, user_id: i64) -> Result<bool, AppError> {
+    let row = sqlx::query("SELECT 1 FROM user_totp WHERE user_id = ? AND confirmed_at IS NOT NULL")
+        .bind(user_id)
+        .fetch_optional(db.get_pool())
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Consumes a valid TOTP step or recovery code; returns false if neither matches.
+async fn check_second_factor(db: &Database, keys: &KeyStore, user_id: i64, code: &str) -> Result<bool, AppError> {
+    let totp_row = sqlx::query("SELECT secret, last_used_step FROM user_totp WHERE user_id = ? AND confirmed_at IS NOT NULL")
+        .bind(user_id)
+        .fetch_optional(db.get_pool())
+        .await?;
+
+    let totp_row = match totp_row {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+
+    let secret = keys.decrypt_secret(totp_row.get("secret"))?;
+    if let Some(step) = totp::verify_code(&secret, code, totp_row.get("last_used_step")) {
+        // A code is only good once, even within its 30 second window
+        let claimed = sqlx::query("UPDATE user_totp SET last_used_step = ? WHERE user_id = ? AND (last_used_step IS NULL OR last_used_step < ?)")
+            .bind(step)
+            .bind(user_id)
+            .bind(step)
+            .execute(db.get_pool())
+            .await?;
+
+        return Ok(claimed.rows_affected() == 1);
+    }
+
+    let used = sqlx::query(
+        r#"
+        UPDATE totp_recovery_codes SET used_at = datetime('now')
+        WHERE id = (SELECT id FROM totp_recovery_codes WHERE user_id = ? AND code_hash = ? AND used_at IS NULL LIMIT 1)
+        "#
+    )
+    .bind(user_id)
+    .bind(keys.hash_token(&totp::normalize_recovery_code(code)))
+    .execute(db.get_pool())
+    .await?;
+
+    Ok(used.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::Manager;
+
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn enrolling_again_restarts_the_pending_enrollment() {
+        let app = test_support::app().await;
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
+        let db = app.state::<Database>();
+
+        let first = enroll_totp(app.state(), app.state(), session.access_token.clone()).await.unwrap();
+        sqlx::query("UPDATE user_totp SET created_at = datetime('now', '-2 days') WHERE user_id = ?")
+            .bind(user_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        let second = enroll_totp(app.state(), app.state(), session.access_token).await.unwrap();
+        assert_ne!(second.secret, first.secret);
+
+        // The stale-enrollment sweep only removes enrollments older than a day
+        let row = sqlx::query("SELECT secret, created_at > datetime('now', '-1 day') AS fresh FROM user_totp WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert!(row.get::<bool, _>("fresh"));
+        assert_eq!(app.state::<KeyStore>().decrypt_secret(row.get("secret")).unwrap(), second.secret);
+    }
+}
@@ -1,14 +1,363 @@
 use tauri::State;
 use sqlx::Row;
+use serde_json::json;
+
+use crate::audit::{self, AuditEventType, AuditOutcome};
+use crate::avatars::{is_stored_avatar, AvatarStore};
 use crate::database::Database;
+use crate::error::AppError;
+use crate::export;
+use crate::keys::KeyStore;
+use crate::session::{authenticate, revoke_all_sessions};
 use crate::models::*;
+use crate::privacy::DISCOVERABLE_SQL;
+use crate::profile::{self, normalize_handle, FieldError};
+use crate::roles::Role;
+
+const MIN_SEARCH_CHARS: usize = 2;
+const MAX_SEARCH_RESULTS: i64 = 50;
 
 // User commands
 #[tauri::command]
 pub async fn get_user_profile(
     db: State<'_, Database>,
-    user_id: i64,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+) -> Result<User, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    fetch_user(&db, user_id).await
+}
+
+#[tauri::command]
+pub async fn update_user_profile(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    avatars: State<'_, AvatarStore>,
+    access_token: String,
+    update_data: UserUpdate,
 ) -> Result<User, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let mut changes = profile::validate(&update_data)?;
+
+    // Stored avatars can be kept but only set through upload_avatar
+    if let Some(Some(avatar_url)) = &changes.avatar_url {
+        if is_stored_avatar(avatar_url) {
+            let current = fetch_user(&db, user_id).await?.avatar_url;
+            if current.as_deref() != Some(avatar_url.as_str()) {
+                return Err(AppError::Validation("avatar_url: Upload images with upload_avatar".to_string()).into());
+            }
+            changes.avatar_url = None;
+        }
+    }
+
+    if changes.is_empty() {
+        return fetch_user(&db, user_id).await;
+    }
+
+    let goals_json = changes.goals.as_ref()
+        .map(|goals| serde_json::to_string(goals).map_err(|e| e.to_string()))
+        .transpose()?;
+
+    // One statement, so a profile is never left half-updated
+    let mut tx = db.get_pool().begin().await.map_err(|e| e.to_string())?;
+
+    sqlx::query(
+        r#"
+        UPDATE users SET
+            handle = CASE WHEN ? THEN ? ELSE handle END,
+            first_name = COALESCE(?, first_name),
+            last_name = COALESCE(?, last_name),
+            avatar_url = CASE WHEN ? THEN ? ELSE avatar_url END,
+            goals = COALESCE(?, goals),
+            updated_at = datetime('now')
+        WHERE id = ?
+        "#
+    )
+    .bind(changes.handle.is_some())
+    .bind(changes.handle.clone().flatten())
+    .bind(&changes.first_name)
+    .bind(&changes.last_name)
+    .bind(changes.avatar_url.is_some())
+    .bind(changes.avatar_url.clone().flatten())
+    .bind(goals_json)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => {
+            AppError::Conflict("handle: This handle is already taken".to_string())
+        }
+        e => AppError::Database(e),
+    })?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    if let Some(avatar_url) = &changes.avatar_url {
+        avatars.remove_for_user(user_id, avatar_url.as_deref());
+    }
+
+    // Field names only; profile values are not security relevant
+    audit::record(&db, Some(user_id), AuditEventType::ProfileUpdated, AuditOutcome::Success, json!({ "fields": changes.fields() })).await?;
+
+    fetch_user(&db, user_id).await
+}
+
+/// Replaces the caller's avatar with an uploaded PNG, JPEG, WebP or GIF
+/// image; see `AvatarStore::save` for the checks applied.
+#[tauri::command]
+pub async fn upload_avatar(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    avatars: State<'_, AvatarStore>,
+    access_token: String,
+    image: Vec<u8>,
+) -> Result<User, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let store = avatars.inner().clone();
+    let avatar_url = tokio::task::spawn_blocking(move || store.save(user_id, &image))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let updated = sqlx::query("UPDATE users SET avatar_url = ?, updated_at = datetime('now') WHERE id = ?")
+        .bind(&avatar_url)
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await;
+
+    // Whichever files are not referenced afterwards go
+    match &updated {
+        Ok(_) => avatars.remove_for_user(user_id, Some(&avatar_url)),
+        Err(_) => avatars.remove_for_user(user_id, fetch_user(&db, user_id).await?.avatar_url.as_deref()),
+    }
+    updated.map_err(|e| e.to_string())?;
+
+    audit::record(&db, Some(user_id), AuditEventType::ProfileUpdated, AuditOutcome::Success, json!({ "fields": ["avatar_url"] })).await?;
+
+    fetch_user(&db, user_id).await
+}
+
+/// Finds other active accounts by handle or display name. Accounts that
+/// are not discoverable only match their exact handle; emails are never
+/// searched or returned.
+#[tauri::command]
+pub async fn search_users(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    query: String,
+    limit: Option<i64>,
+) -> Result<Vec<UserSummary>, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let query = query.trim();
+    if query.chars().count() < MIN_SEARCH_CHARS {
+        return Err(AppError::Validation(format!("Search for at least {} characters", MIN_SEARCH_CHARS)).into());
+    }
+
+    let handle = normalize_handle(query);
+    let escape = |value: &str| value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    let handle_prefix = format!("{}%", escape(&handle));
+    let name_pattern = format!("%{}%", escape(query));
+
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT u.id, u.handle, u.first_name, u.last_name, u.avatar_url
+        FROM users u
+        WHERE u.is_active = 1 AND u.id != ?
+          AND (u.handle = ? OR ({} AND (
+              u.handle LIKE ? ESCAPE '\' OR (u.first_name || ' ' || u.last_name) LIKE ? ESCAPE '\'
+          )))
+        ORDER BY u.handle = ? DESC, u.handle LIKE ? ESCAPE '\' DESC, u.first_name, u.last_name
+        LIMIT ?
+        "#,
+        DISCOVERABLE_SQL
+    ))
+    .bind(user_id)
+    .bind(&handle)
+    .bind(&handle_prefix)
+    .bind(&name_pattern)
+    .bind(&handle)
+    .bind(&handle_prefix)
+    .bind(limit.unwrap_or(20).clamp(1, MAX_SEARCH_RESULTS))
+    .fetch_all(db.get_pool())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(rows.into_iter().map(|row| UserSummary {
+        id: row.get("id"),
+        handle: row.get("handle"),
+        first_name: row.get("first_name"),
+        last_name: row.get("last_name"),
+        avatar_url: row.get("avatar_url"),
+    }).collect())
+}
+
+/// Writes a ZIP of everything stored about the caller to `destination`, a
+/// new `.zip` path chosen by the user, and returns its manifest.
+#[tauri::command]
+pub async fn export_user_data(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    access_token: String,
+    destination: String,
+) -> Result<ExportManifest, String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    let manifest = export::write_archive(&db, user_id, std::path::Path::new(&destination)).await?;
+
+    let rows: u64 = manifest.files.iter().filter(|file| file.format == "json").map(|file| file.rows).sum();
+    audit::record(&db, Some(user_id), AuditEventType::DataExported, AuditOutcome::Success, json!({ "rows": rows })).await?;
+
+    Ok(manifest)
+}
+
+/// Lets the profile form show every invalid field at once.
+#[tauri::command]
+pub async fn check_profile_update(
+    update_data: UserUpdate,
+) -> Result<Vec<FieldError>, String> {
+    Ok(profile::check(&update_data))
+}
+
+/// Deactivates the account and schedules its purge after the
+/// `account_deletion_grace_days` setting; signing in again before then
+/// restores it. `permanent` skips the grace period.
+#[tauri::command]
+pub async fn delete_user_account(
+    db: State<'_, Database>,
+    keys: State<'_, KeyStore>,
+    avatars: State<'_, AvatarStore>,
+    access_token: String,
+    permanent: Option<bool>,
+) -> Result<(), String> {
+    let user_id = authenticate(&db, &keys, &access_token).await?.user_id;
+
+    if permanent.unwrap_or(false) {
+        purge_account(&db, &keys, &avatars, user_id, "user_requested").await?;
+        return Ok(());
+    }
+
+    let grace_days: i64 = db.get_setting("account_deletion_grace_days").await
+        .map_err(|e| e.to_string())?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+
+    sqlx::query("UPDATE users SET is_active = 0, deactivated_at = datetime('now'), purge_after = datetime('now', ?), updated_at = datetime('now') WHERE id = ?")
+        .bind(format!("+{} days", grace_days.max(0)))
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    revoke_all_sessions(&db, user_id, None, "account_deactivated").await?;
+
+    audit::record(&db, Some(user_id), AuditEventType::AccountDeactivated, AuditOutcome::Success, json!({ "grace_days": grace_days })).await?;
+
+    Ok(())
+}
+
+// Helper functions
+/// Whether an account may finish signing in: active, or deactivated by its
+/// owner with the grace period still running. Does not restore anything.
+pub(crate) async fn account_can_sign_in(db: &Database, user_id: i64) -> Result<bool, AppError> {
+    let row = sqlx::query(
+        "SELECT 1 FROM users WHERE id = ? AND (is_active = 1 OR (deactivated_at IS NOT NULL AND purge_after > datetime('now')))"
+    )
+    .bind(user_id)
+    .fetch_optional(db.get_pool())
+    .await?;
+
+    Ok(row.is_some())
+}
+
+/// Reactivates an account deactivated by its owner, if its grace period has
+/// not ended yet. Returns false for accounts that cannot be restored.
+pub(crate) async fn restore_deactivated_account(db: &Database, user_id: i64) -> Result<bool, AppError> {
+    let restored = sqlx::query(
+        r#"
+        UPDATE users SET is_active = 1, deactivated_at = NULL, purge_after = NULL, updated_at = datetime('now')
+        WHERE id = ? AND is_active = 0 AND deactivated_at IS NOT NULL AND purge_after > datetime('now')
+        "#
+    )
+    .bind(user_id)
+    .execute(db.get_pool())
+    .await?;
+
+    if restored.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    audit::record(db, Some(user_id), AuditEventType::AccountRestored, AuditOutcome::Success, json!({})).await?;
+
+    sqlx::query("INSERT INTO notifications (user_id, title, message, type) VALUES (?, ?, ?, 'info')")
+        .bind(user_id)
+        .bind("Welcome back")
+        .bind("Your account has been restored and is no longer scheduled for deletion.")
+        .execute(db.get_pool())
+        .await?;
+
+    Ok(true)
+}
+
+/// Permanently deletes accounts whose deactivation grace period has ended.
+/// Returns how many were purged.
+pub(crate) async fn purge_expired_accounts(db: &Database, keys: &KeyStore, avatars: &AvatarStore) -> Result<u64, AppError> {
+    let rows = sqlx::query("SELECT id FROM users WHERE is_active = 0 AND purge_after IS NOT NULL AND purge_after <= datetime('now')")
+        .fetch_all(db.get_pool())
+        .await?;
+
+    for row in &rows {
+        purge_account(db, keys, avatars, row.get("id"), "grace_period_expired").await?;
+    }
+
+    Ok(rows.len() as u64)
+}
+
+/// Deletes a user (related rows cascade) and their avatar files, and leaves
+/// an `account_purges` record. Only a keyed hash of the email is kept,
+/// enough to answer whether a given address was purged.
+async fn purge_account(db: &Database, keys: &KeyStore, avatars: &AvatarStore, user_id: i64, reason: &str) -> Result<(), AppError> {
+    let user_row = sqlx::query("SELECT email, deactivated_at FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(db.get_pool())
+        .await?;
+
+    let user_row = match user_row {
+        Some(row) => row,
+        None => return Ok(()),
+    };
+
+    let email: String = user_row.get("email");
+    let deactivated_at: Option<chrono::DateTime<chrono::Utc>> = user_row.get("deactivated_at");
+
+    let mut tx = db.get_pool().begin().await?;
+
+    sqlx::query("INSERT INTO account_purges (user_id, email_hash, reason, deactivated_at) VALUES (?, ?, ?, ?)")
+        .bind(user_id)
+        .bind(audit::email_hash(keys, &email))
+        .bind(reason)
+        .bind(deactivated_at)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    avatars.remove_for_user(user_id, None);
+
+    audit::record(db, Some(user_id), AuditEventType::AccountPurged, AuditOutcome::Success, json!({ "reason": reason })).await?;
+
+    Ok(())
+}
+
+pub(crate) async fn fetch_user(db: &Database, user_id: i64) -> Result<User, String> {
     let user_row = sqlx::query("SELECT * FROM users WHERE id = ?")
         .bind(user_id)
         .fetch_one(db.get_pool())
@@ -18,88 +367,127 @@ pub async fn get_user_profile(
     Ok(User {
         id: user_row.get("id"),
         email: user_row.get("email"),
+        handle: user_row.get("handle"),
         first_name: user_row.get("first_name"),
         last_name: user_row.get("last_name"),
         avatar_url: user_row.get("avatar_url"),
-        goals: serde_json::from_str(&user_row.get::<String, _>("goals")).unwrap_or_default(),
+        goals: serde_json::from_str(user_row.get::<Option<&str>, _>("goals").unwrap_or("[]")).unwrap_or_default(),
         is_active: user_row.get("is_active"),
         email_verified: user_row.get("email_verified"),
+        // An unknown value grants nothing, as in role_of
+        role: user_row.try_get("role").unwrap_or(Role::User),
         created_at: user_row.get("created_at"),
         updated_at: user_row.get("updated_at"),
     })
 }
 
-#[tauri::command]
-pub async fn update_user_profile(
-    db: State<'_, Database>,
-    user_id: i64,
-    update_data: UserUpdate,
-) -> Result<User, String> {
-    let mut has_updates = false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::Manager;
 
-    if let Some(first_name) = &update_data.first_name {
-        sqlx::query("UPDATE users SET first_name = ? WHERE id = ?")
+    use crate::test_support;
+
+    async fn set_handle(db: &Database, user_id: i64, handle: &str, first_name: &str, discoverable: bool) {
+        sqlx::query("UPDATE users SET handle = ?, first_name = ? WHERE id = ?")
+            .bind(handle)
             .bind(first_name)
             .bind(user_id)
             .execute(db.get_pool())
             .await
-            .map_err(|e| e.to_string())?;
-        has_updates = true;
-    }
-
-    if let Some(last_name) = &update_data.last_name {
-        sqlx::query("UPDATE users SET last_name = ? WHERE id = ?")
-            .bind(last_name)
+            .unwrap();
+        sqlx::query("INSERT INTO privacy_settings (user_id, discoverable) VALUES (?, ?) ON CONFLICT(user_id) DO UPDATE SET discoverable = excluded.discoverable")
             .bind(user_id)
+            .bind(discoverable)
             .execute(db.get_pool())
             .await
-            .map_err(|e| e.to_string())?;
-        has_updates = true;
+            .unwrap();
     }
 
-    if let Some(avatar_url) = &update_data.avatar_url {
-        sqlx::query("UPDATE users SET avatar_url = ? WHERE id = ?")
-            .bind(avatar_url)
-            .bind(user_id)
-            .execute(db.get_pool())
-            .await
-            .map_err(|e| e.to_string())?;
-        has_updates = true;
+    #[tokio::test]
+    async fn deactivated_accounts_are_signed_out_and_restorable_during_the_grace_period() {
+        let app = test_support::app().await;
+        let db = app.state::<Database>();
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
+
+        delete_user_account(app.state(), app.state(), app.state(), session.access_token.clone(), None).await.unwrap();
+
+        assert!(authenticate(&db, &app.state::<KeyStore>(), &session.access_token).await.is_err());
+        assert!(!fetch_user(&db, user_id).await.unwrap().is_active);
+        assert!(account_can_sign_in(&db, user_id).await.unwrap());
+
+        assert!(restore_deactivated_account(&db, user_id).await.unwrap());
+        assert!(fetch_user(&db, user_id).await.unwrap().is_active);
+        // Only deactivated accounts are restored
+        assert!(!restore_deactivated_account(&db, user_id).await.unwrap());
     }
 
-    if let Some(goals) = &update_data.goals {
-        let goals_json = serde_json::to_string(goals).unwrap();
-        sqlx::query("UPDATE users SET goals = ? WHERE id = ?")
-            .bind(goals_json)
+    #[tokio::test]
+    async fn accounts_are_purged_once_the_grace_period_ends() {
+        let app = test_support::app().await;
+        let db = app.state::<Database>();
+        let keys = app.state::<KeyStore>();
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let other_id = test_support::create_user(&app, "john@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
+
+        delete_user_account(app.state(), app.state(), app.state(), session.access_token, None).await.unwrap();
+        assert_eq!(purge_expired_accounts(&db, &keys, &app.state()).await.unwrap(), 0);
+
+        sqlx::query("UPDATE users SET purge_after = datetime('now', '-1 minute') WHERE id = ?")
             .bind(user_id)
             .execute(db.get_pool())
             .await
-            .map_err(|e| e.to_string())?;
-        has_updates = true;
-    }
+            .unwrap();
+        assert!(!account_can_sign_in(&db, user_id).await.unwrap());
+        assert!(!restore_deactivated_account(&db, user_id).await.unwrap());
+
+        assert_eq!(purge_expired_accounts(&db, &keys, &app.state()).await.unwrap(), 1);
+        assert!(fetch_user(&db, user_id).await.is_err());
+        assert!(fetch_user(&db, other_id).await.is_ok());
 
-    if has_updates {
-        sqlx::query("UPDATE users SET updated_at = datetime('now') WHERE id = ?")
+        let purge = sqlx::query("SELECT email_hash, reason FROM account_purges WHERE user_id = ?")
             .bind(user_id)
-            .execute(db.get_pool())
+            .fetch_one(db.get_pool())
             .await
-            .map_err(|e| e.to_string())?;
+            .unwrap();
+        assert_eq!(purge.get::<String, _>("email_hash"), audit::email_hash(&keys, "jane@example.com"));
+        assert_eq!(purge.get::<String, _>("reason"), "grace_period_expired");
     }
 
-    get_user_profile(db, user_id).await
-}
+    #[tokio::test]
+    async fn permanent_deletion_skips_the_grace_period() {
+        let app = test_support::app().await;
+        let db = app.state::<Database>();
+        let user_id = test_support::create_user(&app, "jane@example.com").await;
+        let session = test_support::sign_in(&app, user_id).await;
 
-#[tauri::command]
-pub async fn delete_user_account(
-    db: State<'_, Database>,
-    user_id: i64,
-) -> Result<(), String> {
-    // Delete user (cascade will handle related records)
-    sqlx::query("DELETE FROM users WHERE id = ?")
-        .bind(user_id)
-        .execute(db.get_pool())
-        .await
-        .map_err(|e| e.to_string())?;
+        delete_user_account(app.state(), app.state(), app.state(), session.access_token, Some(true)).await.unwrap();
 
-    Ok(())
-}
\ No newline at end of file
+        assert!(fetch_user(&db, user_id).await.is_err());
+        assert!(!account_can_sign_in(&db, user_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn search_respects_discoverability_and_never_matches_emails() {
+        let app = test_support::app().await;
+        let db = app.state::<Database>();
+        let user_id = test_support::create_user(&app, "searcher@example.com").await;
+        let open_id = test_support::create_user(&app, "open@example.com").await;
+        let hidden_id = test_support::create_user(&app, "hidden@example.com").await;
+        set_handle(&db, open_id, "runner_anna", "Anna", true).await;
+        set_handle(&db, hidden_id, "runner_bob", "Bob", false).await;
+        let session = test_support::sign_in(&app, user_id).await;
+
+        let search = |query: &str| search_users(app.state(), app.state(), session.access_token.clone(), query.to_string(), None);
+        let ids = |users: Vec<UserSummary>| users.into_iter().map(|user| user.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(search("runner").await.unwrap()), vec![open_id]);
+        assert_eq!(ids(search("Anna").await.unwrap()), vec![open_id]);
+        // An exact handle finds even accounts that are not discoverable
+        assert_eq!(ids(search("@Runner_Bob").await.unwrap()), vec![hidden_id]);
+        assert!(search("example.com").await.unwrap().is_empty());
+        assert!(search("a").await.is_err());
+    }
+}
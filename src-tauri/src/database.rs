@@ -1,4 +1,4 @@
-use sqlx::SqlitePool;
+use sqlx::{Row, SqlitePool};
 use anyhow::Result;
 use std::fs;
 use tauri::{AppHandle, Manager};
@@ -57,13 +57,54 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Soft-deleted accounts keep their row until the grace period ends
+        self.add_column_if_missing("users", "deactivated_at", "DATETIME").await?;
+        self.add_column_if_missing("users", "purge_after", "DATETIME").await?;
+
+        // Roles gate administrative commands. A new install's first account
+        // becomes admin (see NEW_ACCOUNT_ROLE_SQL); an install without any
+        // admin, e.g. one upgraded from before roles, promotes its oldest
+        // active account so admin commands stay reachable
+        self.add_column_if_missing("users", "role", "TEXT NOT NULL DEFAULT 'user'").await?;
+        sqlx::query(
+            r#"
+            UPDATE users SET role = 'admin'
+            WHERE id = (SELECT id FROM users WHERE is_active = 1 ORDER BY created_at, id LIMIT 1)
+              AND NOT EXISTS (SELECT 1 FROM users WHERE role = 'admin')
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Optional public handle, stored lowercase; several accounts may have none
+        self.add_column_if_missing("users", "handle", "TEXT").await?;
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_users_handle ON users (handle)")
+            .execute(&self.pool)
+            .await?;
+
+        // Create account_purges table (audit trail of permanently deleted accounts)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS account_purges (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                email_hash TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                deactivated_at DATETIME,
+                purged_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Create refresh_tokens table
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS refresh_tokens (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 user_id INTEGER NOT NULL,
-                token TEXT NOT NULL,
+                token_hash TEXT NOT NULL,
                 expires_at DATETIME NOT NULL,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
                 FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
@@ -73,13 +114,58 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Create refresh_token_families table (one family per session)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS refresh_token_families (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                revoked_at DATETIME,
+                revoked_reason TEXT,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Refresh tokens are looked up by the jti claim they carry, and
+        // rotation keeps the old row (rotated_at set) linked to its successor
+        self.add_column_if_missing("refresh_tokens", "jti", "TEXT").await?;
+        self.add_column_if_missing("refresh_tokens", "family_id", "INTEGER REFERENCES refresh_token_families (id) ON DELETE CASCADE").await?;
+        self.add_column_if_missing("refresh_tokens", "parent_id", "INTEGER").await?;
+        self.add_column_if_missing("refresh_tokens", "rotated_at", "DATETIME").await?;
+
+        // Sessions describe the device they were started from
+        self.add_column_if_missing("refresh_token_families", "device_label", "TEXT").await?;
+        self.add_column_if_missing("refresh_token_families", "client_info", "TEXT").await?;
+        self.add_column_if_missing("refresh_token_families", "last_used_at", "DATETIME").await?;
+
         // Create password_reset_tokens table
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS password_reset_tokens (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 user_id INTEGER NOT NULL,
-                token TEXT NOT NULL,
+                token_hash TEXT NOT NULL,
+                expires_at DATETIME NOT NULL,
+                used BOOLEAN DEFAULT 0,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create email_verification_tokens table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS email_verification_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                token_hash TEXT NOT NULL,
                 expires_at DATETIME NOT NULL,
                 used BOOLEAN DEFAULT 0,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
@@ -90,6 +176,123 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Create login_codes table (passwordless sign-in)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS login_codes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                code_hash TEXT NOT NULL,
+                expires_at DATETIME NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                used_at DATETIME,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Expiries are compared against datetime('now'), so they must use its
+        // format; older versions wrote RFC 3339 text
+        for table in ["refresh_tokens", "login_codes"] {
+            sqlx::query(&format!("UPDATE {} SET expires_at = datetime(expires_at) WHERE expires_at LIKE '%T%'", table))
+                .execute(&self.pool)
+                .await?;
+        }
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_email_verification_tokens_token_hash ON email_verification_tokens (token_hash)")
+            .execute(&self.pool)
+            .await?;
+
+        // Older versions stored bearer tokens in plaintext; those rows are
+        // dropped (forcing a fresh login / reset request) and only keyed
+        // hashes are stored from now on
+        for table in ["refresh_tokens", "password_reset_tokens"] {
+            if self.column_exists(table, "token").await? {
+                sqlx::query(&format!("DELETE FROM {}", table))
+                    .execute(&self.pool)
+                    .await?;
+                sqlx::query(&format!("ALTER TABLE {} DROP COLUMN token", table))
+                    .execute(&self.pool)
+                    .await?;
+                self.add_column_if_missing(table, "token_hash", "TEXT NOT NULL DEFAULT ''").await?;
+            }
+        }
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_refresh_tokens_token_hash ON refresh_tokens (token_hash)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_password_reset_tokens_token_hash ON password_reset_tokens (token_hash)")
+            .execute(&self.pool)
+            .await?;
+
+        // Create login_attempts table (failed-login tracking per email / client)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS login_attempts (
+                key TEXT PRIMARY KEY,
+                failure_count INTEGER NOT NULL DEFAULT 0,
+                last_failure_at DATETIME NOT NULL,
+                locked_until DATETIME
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create user_identities table (accounts at external identity providers)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS user_identities (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                issuer TEXT NOT NULL,
+                subject TEXT NOT NULL,
+                email TEXT,
+                last_login_at DATETIME,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (issuer, subject),
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create user_totp table (one authenticator per user)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS user_totp (
+                user_id INTEGER PRIMARY KEY,
+                secret TEXT NOT NULL,
+                confirmed_at DATETIME,
+                last_used_step INTEGER,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create totp_recovery_codes table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS totp_recovery_codes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                code_hash TEXT NOT NULL,
+                used_at DATETIME,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Create progress table
         sqlx::query(
             r#"
@@ -129,6 +332,41 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Create audit_events table (append-only; rows only leave through retention pruning)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                actor_user_id INTEGER,
+                event_type TEXT NOT NULL,
+                outcome TEXT NOT NULL,
+                metadata TEXT NOT NULL DEFAULT '{}',
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS audit_events_no_update
+            BEFORE UPDATE ON audit_events
+            BEGIN
+                SELECT RAISE(ABORT, 'audit_events is append-only');
+            END
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_events_actor ON audit_events (actor_user_id, created_at)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_events_created_at ON audit_events (created_at)")
+            .execute(&self.pool)
+            .await?;
+
         // Create subscriptions table
         sqlx::query(
             r#"
@@ -168,6 +406,67 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Create privacy_settings table (no row: every default applies)
+        let privacy_settings_existed = self.table_exists("privacy_settings").await?;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS privacy_settings (
+                user_id INTEGER PRIMARY KEY,
+                default_visibility TEXT NOT NULL DEFAULT 'friends_only',
+                share_notes BOOLEAN NOT NULL DEFAULT 0,
+                show_on_leaderboard BOOLEAN NOT NULL DEFAULT 1,
+                discoverable BOOLEAN NOT NULL DEFAULT 1,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Accounts from before privacy settings shared all their progress;
+        // they keep doing so until they choose otherwise
+        if !privacy_settings_existed {
+            sqlx::query("INSERT INTO privacy_settings (user_id, default_visibility) SELECT id, 'public' FROM users")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        self.add_column_if_missing("privacy_settings", "discoverable", "BOOLEAN NOT NULL DEFAULT 1").await?;
+
+        // Create privacy_category_visibility table (per-category overrides)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS privacy_category_visibility (
+                user_id INTEGER NOT NULL,
+                category TEXT NOT NULL,
+                visibility TEXT NOT NULL,
+                PRIMARY KEY (user_id, category),
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create user_preferences table (NULL columns follow the global settings)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS user_preferences (
+                user_id INTEGER PRIMARY KEY,
+                unit_system TEXT,
+                timezone TEXT,
+                week_start TEXT,
+                email_notifications BOOLEAN,
+                progress_page_size INTEGER,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Create settings table
         sqlx::query(
             r#"
@@ -190,7 +489,33 @@ impl Database {
             INSERT OR IGNORE INTO settings (key, value, description) VALUES
             ('max_progress_display', '100', 'Maximum number of progress entries to display'),
             ('default_challenge_duration', '30', 'Default challenge duration in days'),
-            ('email_notifications_enabled', 'true', 'Enable email notifications by default')
+            ('email_notifications_enabled', 'true', 'Enable email notifications by default'),
+            ('require_verified_email_for_invites', 'true', 'Only accounts with a verified email can invite friends'),
+            ('require_verified_email_for_leaderboard', 'false', 'Only accounts with a verified email appear on leaderboards'),
+            ('password_min_length', '8', 'Minimum password length in characters'),
+            ('password_max_bytes', '72', 'Maximum password length in bytes'),
+            ('password_require_lowercase', 'true', 'Passwords must contain a lowercase letter'),
+            ('password_require_uppercase', 'true', 'Passwords must contain an uppercase letter'),
+            ('password_require_digit', 'true', 'Passwords must contain a digit'),
+            ('password_require_symbol', 'false', 'Passwords must contain a symbol'),
+            ('password_reject_personal_info', 'true', 'Reject passwords containing the account email or name'),
+            ('password_reject_common', 'true', 'Reject passwords from the bundled common-password list'),
+            ('login_backoff_threshold', '3', 'Failed logins before each further attempt is delayed exponentially'),
+            ('login_lockout_threshold', '10', 'Failed logins before sign-in is temporarily locked'),
+            ('login_lockout_minutes', '15', 'How long a sign-in lockout lasts'),
+            ('password_hash_memory_kib', '19456', 'Argon2id memory cost in KiB for password hashes'),
+            ('password_hash_iterations', '2', 'Argon2id iteration count for password hashes'),
+            ('password_hash_parallelism', '1', 'Argon2id parallelism for password hashes'),
+            ('account_deletion_grace_days', '30', 'Days a deleted account can be restored by signing in before it is purged'),
+            ('audit_retention_days', '365', 'Days security audit events are kept'),
+            ('passwordless_login_enabled', 'true', 'Allow signing in with a one-time code sent by email'),
+            ('login_code_ttl_minutes', '10', 'Minutes a sign-in code stays valid'),
+            ('login_code_max_attempts', '5', 'Wrong guesses before a sign-in code is invalidated'),
+            ('maintenance_interval_minutes', '60', 'Minutes between background sweeps of expired and stale data'),
+            ('notification_retention_days', '90', 'Days read notifications are kept'),
+            ('default_unit_system', 'metric', 'Unit system for users who have not chosen one (metric or imperial)'),
+            ('default_timezone', 'UTC', 'IANA time zone for users who have not chosen one'),
+            ('default_week_start', 'monday', 'First day of the week for users who have not chosen one')
             "#
         )
         .execute(&self.pool)
@@ -199,7 +524,126 @@ impl Database {
         Ok(())
     }
 
+    // Brings tables created by an older version up to date
+    async fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        if !self.column_exists(table, column).await? {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn table_exists(&self, table: &str) -> Result<bool> {
+        let row = sqlx::query("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(table)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn column_exists(&self, table: &str, column: &str) -> Result<bool> {
+        let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(columns.iter().any(|row| row.get::<String, _>("name") == column))
+    }
+
+    pub async fn get_setting(&self, key: &str) -> Result<Option<String>, sqlx::Error> {
+        let row = sqlx::query("SELECT value FROM settings WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|row| row.get("value")))
+    }
+
+    pub async fn setting_enabled(&self, key: &str, default: bool) -> Result<bool, sqlx::Error> {
+        Ok(self.get_setting(key).await?
+            .map(|value| value == "true")
+            .unwrap_or(default))
+    }
+
     pub fn get_pool(&self) -> &SqlitePool {
         &self.pool
     }
 }
+
+#[cfg(test)]
+impl Database {
+    /// A private in-memory database with every migration applied. One
+    /// connection that never closes, since each would get its own database.
+    pub async fn in_memory() -> Result<Self> {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+
+        let db = Database { pool };
+        db.run_migrations().await?;
+
+        Ok(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Row;
+
+    #[tokio::test]
+    async fn promotes_the_oldest_active_account_when_no_admin_exists() {
+        let db = Database::in_memory().await.unwrap();
+        for (email, created_at, is_active) in [
+            ("deactivated@example.com", "2020-01-01 00:00:00", false),
+            ("oldest@example.com", "2021-01-01 00:00:00", true),
+            ("newer@example.com", "2022-01-01 00:00:00", true),
+        ] {
+            sqlx::query("INSERT INTO users (email, password_hash, first_name, last_name, created_at, is_active) VALUES (?, 'x', 'Jane', 'Doe', ?, ?)")
+                .bind(email)
+                .bind(created_at)
+                .bind(is_active)
+                .execute(db.get_pool())
+                .await
+                .unwrap();
+        }
+
+        db.run_migrations().await.unwrap();
+        // Later runs leave the existing admin alone
+        db.run_migrations().await.unwrap();
+
+        let admins: Vec<String> = sqlx::query("SELECT email FROM users WHERE role = 'admin'")
+            .fetch_all(db.get_pool())
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("email"))
+            .collect();
+        assert_eq!(admins, ["oldest@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn existing_accounts_keep_sharing_progress_publicly() {
+        let db = Database::in_memory().await.unwrap();
+        sqlx::query("INSERT INTO users (email, password_hash, first_name, last_name) VALUES ('jane@example.com', 'x', 'Jane', 'Doe')")
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        // As on an install from before privacy settings
+        sqlx::query("DROP TABLE privacy_settings").execute(db.get_pool()).await.unwrap();
+        db.run_migrations().await.unwrap();
+
+        let visibility: String = sqlx::query("SELECT default_visibility FROM privacy_settings")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("default_visibility");
+        assert_eq!(visibility, "public");
+    }
+}
@@ -16,6 +16,10 @@ pub enum AppError {
     
     #[error("Unauthorized: {0}")]
     Unauthorized(String),
+
+    // Lets the frontend tell a token worth refreshing from a rejected one
+    #[error("Token expired: {0}")]
+    TokenExpired(String),
     
     #[error("Conflict: {0}")]
     Conflict(String),
@@ -0,0 +1,455 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use serde_json::{json, Map, Value};
+use sqlx::query::Query;
+use sqlx::sqlite::{SqliteArguments, SqliteConnection, SqliteRow};
+use sqlx::{Row, Sqlite};
+use tokio::sync::mpsc;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::commands::users::fetch_user;
+use crate::database::Database;
+use crate::error::AppError;
+use crate::models::{ExportManifest, ExportedFile};
+use crate::{preferences, privacy};
+
+/// Bumped whenever a file, column or field of the archive changes meaning.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Chunks in flight between the database reader and the archive writer
+const CHANNEL_CAPACITY: usize = 64;
+
+/// What the reader hands the writer: start the next file, or append to it.
+enum Chunk {
+    File(String),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Integer,
+    Real,
+    Text,
+    Boolean,
+}
+
+/// One tabular export, written as both `<name>.json` and `<name>.csv`.
+/// Every query takes the user id once per `?`.
+struct Table {
+    name: &'static str,
+    sql: &'static str,
+    binds: usize,
+    columns: &'static [(&'static str, Kind)],
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "progress",
+        sql: "SELECT id, category, metric, value, unit, notes, date, created_at, updated_at FROM progress WHERE user_id = ? ORDER BY date, id",
+        binds: 1,
+        columns: &[
+            ("id", Kind::Integer),
+            ("category", Kind::Text),
+            ("metric", Kind::Text),
+            ("value", Kind::Real),
+            ("unit", Kind::Text),
+            ("notes", Kind::Text),
+            ("date", Kind::Text),
+            ("created_at", Kind::Text),
+            ("updated_at", Kind::Text),
+        ],
+    },
+    Table {
+        name: "notifications",
+        sql: "SELECT id, title, message, type, is_read, created_at FROM notifications WHERE user_id = ? ORDER BY id",
+        binds: 1,
+        columns: &[
+            ("id", Kind::Integer),
+            ("title", Kind::Text),
+            ("message", Kind::Text),
+            ("type", Kind::Text),
+            ("is_read", Kind::Boolean),
+            ("created_at", Kind::Text),
+        ],
+    },
+    // The other side is identified by id, handle and name; never by email
+    Table {
+        name: "friendships",
+        sql: r#"
+            SELECT f.id, CASE WHEN f.user_id = ? THEN 'sent' ELSE 'received' END AS direction,
+                   o.id AS other_user_id, o.handle AS other_handle,
+                   o.first_name AS other_first_name, o.last_name AS other_last_name,
+                   f.status, f.created_at
+            FROM user_friends f
+            JOIN users o ON o.id = CASE WHEN f.user_id = ? THEN f.friend_id ELSE f.user_id END
+            WHERE f.user_id = ? OR f.friend_id = ?
+            ORDER BY f.id
+        "#,
+        binds: 4,
+        columns: &[
+            ("id", Kind::Integer),
+            ("direction", Kind::Text),
+            ("other_user_id", Kind::Integer),
+            ("other_handle", Kind::Text),
+            ("other_first_name", Kind::Text),
+            ("other_last_name", Kind::Text),
+            ("status", Kind::Text),
+            ("created_at", Kind::Text),
+        ],
+    },
+    Table {
+        name: "subscriptions",
+        sql: r#"
+            SELECT id, stripe_customer_id, stripe_subscription_id, status, plan_type,
+                   current_period_start, current_period_end, created_at, updated_at
+            FROM subscriptions WHERE user_id = ? ORDER BY id
+        "#,
+        binds: 1,
+        columns: &[
+            ("id", Kind::Integer),
+            ("stripe_customer_id", Kind::Text),
+            ("stripe_subscription_id", Kind::Text),
+            ("status", Kind::Text),
+            ("plan_type", Kind::Text),
+            ("current_period_start", Kind::Text),
+            ("current_period_end", Kind::Text),
+            ("created_at", Kind::Text),
+            ("updated_at", Kind::Text),
+        ],
+    },
+];
+
+/// Writes everything stored about a user to a ZIP at `destination`: the
+/// profile as JSON, each table in `TABLES` as JSON and CSV, and a
+/// `manifest.json`. Rows are streamed from one read transaction to a
+/// blocking writer into a temporary file beside `destination`, which is
+/// renamed into place once complete and never replaces an existing file.
+pub async fn write_archive(db: &Database, user_id: i64, destination: &Path) -> Result<ExportManifest, AppError> {
+    check_destination(destination)?;
+    let (partial, file) = create_partial(destination)?;
+
+    let result = write_to(db, user_id, file).await.and_then(|manifest| {
+        // Renaming would replace a file that appeared at the destination meanwhile
+        if destination.exists() {
+            return Err(AppError::Conflict("A file already exists at the export path".to_string()));
+        }
+        fs::rename(&partial, destination).map_err(io_error)?;
+        Ok(manifest)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+async fn write_to(db: &Database, user_id: i64, file: File) -> Result<ExportManifest, AppError> {
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let writer = tokio::task::spawn_blocking(move || write_chunks(file, receiver));
+
+    let produced = produce(db, user_id, &sender).await;
+    drop(sender);
+
+    // A failed writer is the reason sending stopped, so report it first
+    writer.await.map_err(|e| AppError::Internal(e.to_string()))??;
+    produced
+}
+
+async fn produce(db: &Database, user_id: i64, sender: &mpsc::Sender<Chunk>) -> Result<ExportManifest, AppError> {
+    let mut files = Vec::new();
+
+    // Password hashes, TOTP secrets and tokens are credentials, not personal data
+    let profile = json!({
+        "user": fetch_user(db, user_id).await.map_err(AppError::Internal)?,
+        "privacy": privacy::load(db, user_id).await?,
+        "preferences": preferences::load(db, user_id).await?,
+    });
+    send(sender, Chunk::File("profile.json".to_string())).await?;
+    send(sender, Chunk::Bytes(serde_json::to_vec_pretty(&profile).map_err(json_error)?)).await?;
+    files.push(ExportedFile { path: "profile.json".to_string(), format: "json".to_string(), rows: 1 });
+
+    // Both formats of every table are read from the same snapshot
+    let mut tx = db.get_pool().begin().await?;
+
+    for table in TABLES {
+        let path = format!("{}.json", table.name);
+        send(sender, Chunk::File(path.clone())).await?;
+        let rows = write_json(&mut tx, user_id, table, sender).await?;
+        files.push(ExportedFile { path, format: "json".to_string(), rows });
+
+        let path = format!("{}.csv", table.name);
+        send(sender, Chunk::File(path.clone())).await?;
+        let rows = write_csv(&mut tx, user_id, table, sender).await?;
+        files.push(ExportedFile { path, format: "csv".to_string(), rows });
+    }
+
+    tx.rollback().await?;
+
+    let manifest = ExportManifest {
+        schema_version: SCHEMA_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        user_id,
+        generated_at: Utc::now(),
+        files,
+    };
+    send(sender, Chunk::File("manifest.json".to_string())).await?;
+    send(sender, Chunk::Bytes(serde_json::to_vec_pretty(&manifest).map_err(json_error)?)).await?;
+
+    Ok(manifest)
+}
+
+// Runs on a blocking thread; the archive is finished once the channel closes
+fn write_chunks(file: File, mut receiver: mpsc::Receiver<Chunk>) -> Result<(), AppError> {
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    while let Some(chunk) = receiver.blocking_recv() {
+        match chunk {
+            Chunk::File(path) => zip.start_file(path, options).map_err(zip_error)?,
+            Chunk::Bytes(bytes) => zip.write_all(&bytes).map_err(io_error)?,
+        }
+    }
+
+    zip.finish().map_err(zip_error)?
+        .into_inner().map_err(|e| io_error(e.into_error()))?
+        .sync_all().map_err(io_error)
+}
+
+async fn send(sender: &mpsc::Sender<Chunk>, chunk: Chunk) -> Result<(), AppError> {
+    sender.send(chunk).await
+        .map_err(|_| AppError::Internal("Export writer stopped".to_string()))
+}
+
+fn table_query<'q>(table: &'q Table, user_id: i64) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    let mut query = sqlx::query(table.sql);
+    for _ in 0..table.binds {
+        query = query.bind(user_id);
+    }
+    query
+}
+
+// A JSON array written one object per line as rows arrive
+async fn write_json(tx: &mut SqliteConnection, user_id: i64, table: &Table, sender: &mpsc::Sender<Chunk>) -> Result<u64, AppError> {
+    let mut rows = table_query(table, user_id).fetch(tx);
+
+    let mut count = 0;
+    send(sender, Chunk::Bytes(b"[".to_vec())).await?;
+    while let Some(row) = rows.try_next().await? {
+        let object: Map<String, Value> = table.columns.iter()
+            .map(|(column, kind)| (column.to_string(), json_value(&row, column, *kind)))
+            .collect();
+
+        let mut line = if count == 0 { b"\n".to_vec() } else { b",\n".to_vec() };
+        serde_json::to_writer(&mut line, &object).map_err(json_error)?;
+        send(sender, Chunk::Bytes(line)).await?;
+        count += 1;
+    }
+    send(sender, Chunk::Bytes(b"\n]\n".to_vec())).await?;
+
+    Ok(count)
+}
+
+async fn write_csv(tx: &mut SqliteConnection, user_id: i64, table: &Table, sender: &mpsc::Sender<Chunk>) -> Result<u64, AppError> {
+    let mut rows = table_query(table, user_id).fetch(tx);
+
+    let header: Vec<&str> = table.columns.iter().map(|(column, _)| *column).collect();
+    send(sender, Chunk::Bytes(csv_line(&header)?)).await?;
+
+    let mut count = 0;
+    while let Some(row) = rows.try_next().await? {
+        let record: Vec<String> = table.columns.iter()
+            .map(|(column, kind)| match json_value(&row, column, *kind) {
+                Value::Null => String::new(),
+                Value::String(text) => text,
+                other => other.to_string(),
+            })
+            .collect();
+        send(sender, Chunk::Bytes(csv_line(&record)?)).await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+fn csv_line<T: AsRef<[u8]>>(fields: &[T]) -> Result<Vec<u8>, AppError> {
+    let mut csv = csv::Writer::from_writer(Vec::new());
+    csv.write_record(fields).map_err(csv_error)?;
+    csv.into_inner().map_err(|e| io_error(e.into_error()))
+}
+
+fn json_value(row: &SqliteRow, column: &str, kind: Kind) -> Value {
+    match kind {
+        Kind::Integer => row.get::<Option<i64>, _>(column).map(Value::from),
+        Kind::Real => row.get::<Option<f64>, _>(column).map(Value::from),
+        Kind::Text => row.get::<Option<String>, _>(column).map(Value::from),
+        Kind::Boolean => row.get::<Option<bool>, _>(column).map(Value::from),
+    }
+    .unwrap_or(Value::Null)
+}
+
+// Refuse anything but a new .zip file in an existing directory
+fn check_destination(destination: &Path) -> Result<(), AppError> {
+    if !destination.is_absolute() {
+        return Err(AppError::Validation("Export path must be absolute".to_string()));
+    }
+    if !destination.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip")) {
+        return Err(AppError::Validation("Export path must end in .zip".to_string()));
+    }
+    if !destination.parent().is_some_and(Path::is_dir) {
+        return Err(AppError::Validation("Export folder does not exist".to_string()));
+    }
+    // Fails early; `write_archive` checks again before moving the file in place
+    if destination.exists() {
+        return Err(AppError::Conflict("A file already exists at the export path".to_string()));
+    }
+    Ok(())
+}
+
+// A new file next to the destination, so the final rename stays on one
+// filesystem; the random suffix keeps it clear of files we did not create
+fn create_partial(destination: &Path) -> Result<(PathBuf, File), AppError> {
+    let mut name = destination.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{:016x}.partial", rand::random::<u64>()));
+    let partial = destination.with_file_name(name);
+
+    let file = File::options().write(true).create_new(true).open(&partial).map_err(io_error)?;
+    Ok((partial, file))
+}
+
+fn io_error(e: std::io::Error) -> AppError {
+    AppError::Internal(format!("Could not write export: {}", e))
+}
+
+fn zip_error(e: zip::result::ZipError) -> AppError {
+    AppError::Internal(format!("Could not write export: {}", e))
+}
+
+fn json_error(e: serde_json::Error) -> AppError {
+    AppError::Internal(format!("Could not write export: {}", e))
+}
+
+fn csv_error(e: csv::Error) -> AppError {
+    AppError::Internal(format!("Could not write export: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    async fn create_user(db: &Database, email: &str, first_name: &str) -> i64 {
+        sqlx::query("INSERT INTO users (email, password_hash, first_name, last_name) VALUES (?, 'secret-hash', ?, 'Doe')")
+            .bind(email)
+            .bind(first_name)
+            .execute(db.get_pool())
+            .await
+            .unwrap()
+            .last_insert_rowid()
+    }
+
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("progress2win-export-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn read_entry(archive: &mut ZipArchive<File>, path: &str) -> String {
+        let mut contents = String::new();
+        archive.by_name(path).unwrap().read_to_string(&mut contents).unwrap();
+        contents
+    }
+
+    #[tokio::test]
+    async fn archives_the_users_data_without_credentials_or_other_emails() {
+        let db = Database::in_memory().await.unwrap();
+        let user_id = create_user(&db, "jane@example.com", "Jane").await;
+        let friend_id = create_user(&db, "john@example.com", "John").await;
+        sqlx::query("INSERT INTO progress (user_id, category, metric, value, unit, notes, date) VALUES (?, 'running', 'distance', 5.5, 'km', 'Easy, then fast', '2024-01-01'), (?, 'reading', 'pages', 40, NULL, NULL, '2024-01-01')")
+            .bind(user_id)
+            .bind(friend_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO user_friends (user_id, friend_id, status) VALUES (?, ?, 'accepted')")
+            .bind(friend_id)
+            .bind(user_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        let dir = scratch_dir();
+        let destination = dir.join("export.zip");
+        let manifest = write_archive(&db, user_id, &destination).await.unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&destination).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+        let mut expected: Vec<&str> = manifest.files.iter().map(|file| file.path.as_str()).chain(["manifest.json"]).collect();
+        expected.sort();
+        assert_eq!(names, expected);
+
+        let profile = read_entry(&mut archive, "profile.json");
+        assert!(profile.contains("jane@example.com"));
+        assert!(!profile.contains("secret-hash"));
+
+        let progress: Vec<Value> = serde_json::from_str(&read_entry(&mut archive, "progress.json")).unwrap();
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0]["value"], json!(5.5));
+        assert_eq!(progress[0]["notes"], json!("Easy, then fast"));
+
+        let progress_csv = read_entry(&mut archive, "progress.csv");
+        assert_eq!(progress_csv.lines().count(), 2);
+        assert!(progress_csv.contains("\"Easy, then fast\""));
+
+        let friendships: Vec<Value> = serde_json::from_str(&read_entry(&mut archive, "friendships.json")).unwrap();
+        assert_eq!(friendships[0]["direction"], json!("received"));
+        assert_eq!(friendships[0]["other_first_name"], json!("John"));
+        assert!(!read_entry(&mut archive, "friendships.csv").contains("john@example.com"));
+
+        let rows = |path: &str| manifest.files.iter().find(|file| file.path == path).unwrap().rows;
+        assert_eq!((rows("progress.json"), rows("progress.csv"), rows("notifications.json")), (1, 1, 0));
+
+        // Only the finished archive is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn never_replaces_or_removes_existing_files() {
+        let db = Database::in_memory().await.unwrap();
+        let user_id = create_user(&db, "jane@example.com", "Jane").await;
+
+        let dir = scratch_dir();
+        let destination = dir.join("export.zip");
+        fs::write(&destination, "keep me").unwrap();
+        let other = dir.join("fresh.zip.partial");
+        fs::write(&other, "not ours").unwrap();
+
+        assert!(matches!(write_archive(&db, user_id, &destination).await, Err(AppError::Conflict(_))));
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "keep me");
+
+        write_archive(&db, user_id, &dir.join("fresh.zip")).await.unwrap();
+        assert_eq!(fs::read_to_string(&other).unwrap(), "not ours");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn accepts_only_new_zip_files_in_existing_folders() {
+        let dir = scratch_dir();
+
+        assert!(matches!(check_destination(Path::new("export.zip")), Err(AppError::Validation(_))));
+        assert!(matches!(check_destination(&dir.join("export.txt")), Err(AppError::Validation(_))));
+        assert!(matches!(check_destination(&dir.join("missing").join("export.zip")), Err(AppError::Validation(_))));
+        assert!(check_destination(&dir.join("export.ZIP")).is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
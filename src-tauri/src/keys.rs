@@ -0,0 +1,310 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit as _, Nonce};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+const KEY_FILE_NAME: &str = "jwt_keys.json";
+const DEFAULT_ROTATION_DAYS: i64 = 30;
+// Must cover the refresh token lifetime so rotation never logs anyone out
+const DEFAULT_GRACE_DAYS: i64 = 30;
+// Marks values written by `encrypt_secret`; base32 and base64 never contain ':'
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SigningKey {
+    kid: String,
+    secret: String, // base64
+    created_at: DateTime<Utc>,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyFile {
+    rotation_days: i64,
+    grace_days: i64,
+    keys: Vec<SigningKey>,
+    // Never rotated: stored token hashes must stay reproducible
+    #[serde(default)]
+    token_hash_key: Option<String>,
+    // Never rotated: secrets encrypted with it must stay readable
+    #[serde(default)]
+    encryption_key: Option<String>,
+}
+
+/// Per-install JWT signing keys, persisted in the app data directory next to
+/// progress2win.db. New tokens are signed with the newest key and carry its
+/// `kid`; retired keys keep verifying tokens until their grace window ends.
+pub struct KeyStore {
+    path: PathBuf,
+    state: RwLock<KeyFile>,
+}
+
+impl KeyStore {
+    pub fn new(app_handle: &AppHandle) -> Result<Self> {
+        let app_data_dir = app_handle
+            .path()
+            .app_data_dir()
+            .expect("Failed to get app data directory");
+
+        if !app_data_dir.exists() {
+            fs::create_dir_all(&app_data_dir)?;
+        }
+
+        let path = app_data_dir.join(KEY_FILE_NAME);
+        let state = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            KeyFile {
+                rotation_days: DEFAULT_ROTATION_DAYS,
+                grace_days: DEFAULT_GRACE_DAYS,
+                keys: Vec::new(),
+                token_hash_key: None,
+                encryption_key: None,
+            }
+        };
+
+        let store = KeyStore { path, state: RwLock::new(state) };
+        store.ensure_install_keys()?;
+        store.rotate_if_due()?;
+
+        Ok(store)
+    }
+
+    /// Signs `claims` with the current key, rotating first if it is due.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, AppError> {
+        self.rotate_if_due()
+            .map_err(|e| AppError::Internal(format!("Key rotation failed: {}", e)))?;
+
+        let state = self.state.read().unwrap();
+        let key = state.keys.iter()
+            .rev()
+            .find(|key| key.retired_at.is_none())
+            .ok_or_else(|| AppError::Internal("No active signing key".to_string()))?;
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(key.kid.clone());
+
+        encode(&header, claims, &EncodingKey::from_secret(&key.secret_bytes()?))
+            .map_err(|e| AppError::Internal(format!("Token generation failed: {}", e)))
+    }
+
+    /// Verifies a token against the key named by its `kid` header. Tokens
+    /// without a `kid`, or whose key has left its grace window, are rejected.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str, validation: &Validation) -> jsonwebtoken::errors::Result<TokenData<T>> {
+        let kid = decode_header(token)?.kid.ok_or(ErrorKind::InvalidToken)?;
+
+        let secret = {
+            let state = self.state.read().unwrap();
+            let grace = Duration::days(state.grace_days);
+            let key = state.keys.iter()
+                .find(|key| key.kid == kid)
+                .filter(|key| key.retired_at.is_none_or(|retired| retired + grace > Utc::now()))
+                .ok_or(ErrorKind::InvalidSignature)?;
+            key.secret_bytes().map_err(|_| ErrorKind::InvalidSignature)?
+        };
+
+        decode::<T>(token, &DecodingKey::from_secret(&secret), validation)
+    }
+
+    /// Keyed hash (HMAC-SHA256) of a bearer secret such as a refresh or
+    /// password reset token, used to store and look it up at rest.
+    pub fn hash_token(&self, token: &str) -> String {
+        let state = self.state.read().unwrap();
+        let key = state.token_hash_key.as_deref().unwrap_or_default();
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Encrypts a secret that has to be read back later, such as a TOTP
+    /// shared secret, with the per-install key (ChaCha20-Poly1305).
+    pub fn encrypt_secret(&self, plaintext: &str) -> Result<String, AppError> {
+        let cipher = self.cipher()?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| AppError::Internal("Could not encrypt secret".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(sealed)))
+    }
+
+    /// Reverses `encrypt_secret`.
+    pub fn decrypt_secret(&self, stored: &str) -> Result<String, AppError> {
+        let invalid = || AppError::Internal("Could not decrypt secret".to_string());
+
+        let sealed = stored.strip_prefix(ENCRYPTED_PREFIX)
+            .and_then(|encoded| STANDARD.decode(encoded).ok())
+            .filter(|sealed| sealed.len() > 12)
+            .ok_or_else(invalid)?;
+        let (nonce, ciphertext) = sealed.split_at(12);
+
+        let plaintext = self.cipher()?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid())?;
+        String::from_utf8(plaintext).map_err(|_| invalid())
+    }
+
+    fn cipher(&self) -> Result<ChaCha20Poly1305, AppError> {
+        let state = self.state.read().unwrap();
+        let key = state.encryption_key.as_deref()
+            .and_then(|key| STANDARD.decode(key).ok())
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| AppError::Internal("Invalid encryption key".to_string()))?;
+
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    /// Generates a new signing key and retires the current one. With
+    /// `revoke_previous`, older keys stop verifying immediately instead of
+    /// after the grace window, which invalidates every outstanding token.
+    pub fn rotate(&self, revoke_previous: bool) -> Result<String> {
+        let mut state = self.state.write().unwrap();
+        self.rotate_locked(&mut state, revoke_previous)
+    }
+
+    fn rotate_if_due(&self) -> Result<()> {
+        if !self.rotation_due(&self.state.read().unwrap()) {
+            return Ok(());
+        }
+
+        // Re-check under the write lock so concurrent callers rotate only once
+        let mut state = self.state.write().unwrap();
+        if self.rotation_due(&state) {
+            self.rotate_locked(&mut state, false)?;
+        }
+        Ok(())
+    }
+
+    fn rotation_due(&self, state: &KeyFile) -> bool {
+        let rotation = Duration::days(state.rotation_days);
+        state.keys.iter()
+            .filter(|key| key.retired_at.is_none())
+            .all(|key| key.created_at + rotation <= Utc::now())
+    }
+
+    fn rotate_locked(&self, state: &mut KeyFile, revoke_previous: bool) -> Result<String> {
+        let now = Utc::now();
+
+        if revoke_previous {
+            state.keys.clear();
+        } else {
+            for key in state.keys.iter_mut().filter(|key| key.retired_at.is_none()) {
+                key.retired_at = Some(now);
+            }
+        }
+
+        // Drop keys whose grace window has ended
+        let grace = Duration::days(state.grace_days);
+        state.keys.retain(|key| key.retired_at.is_none_or(|retired| retired + grace > now));
+
+        let key = SigningKey::generate();
+        let kid = key.kid.clone();
+        state.keys.push(key);
+
+        self.persist(state)?;
+        Ok(kid)
+    }
+
+    fn ensure_install_keys(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        if state.token_hash_key.is_some() && state.encryption_key.is_some() {
+            return Ok(());
+        }
+
+        let random_key = || {
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            Some(STANDARD.encode(secret))
+        };
+        if state.token_hash_key.is_none() {
+            state.token_hash_key = random_key();
+        }
+        if state.encryption_key.is_none() {
+            state.encryption_key = random_key();
+        }
+        self.persist(&state)
+    }
+
+    // Written to a private temporary file, then renamed over the key file,
+    // so the keys are never readable by others or left half written
+    fn persist(&self, state: &KeyFile) -> Result<()> {
+        let temp_path = self.path.with_extension("json.tmp");
+        let _ = fs::remove_file(&temp_path);
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(&temp_path)?;
+        file.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl SigningKey {
+    fn generate() -> Self {
+        let mut secret = [0u8; 64];
+        rand::thread_rng().fill_bytes(&mut secret);
+
+        SigningKey {
+            kid: Uuid::new_v4().simple().to_string(),
+            secret: STANDARD.encode(secret),
+            created_at: Utc::now(),
+            retired_at: None,
+        }
+    }
+
+    fn secret_bytes(&self) -> Result<Vec<u8>, AppError> {
+        STANDARD.decode(&self.secret)
+            .map_err(|e| AppError::Internal(format!("Corrupt signing key {}: {}", self.kid, e)))
+    }
+}
+
+#[cfg(test)]
+impl KeyStore {
+    /// A store with fresh keys, persisted to a temporary file.
+    pub fn ephemeral() -> Self {
+        let path = std::env::temp_dir().join(format!("progress2win-keys-{}.json", Uuid::new_v4().simple()));
+        let store = KeyStore {
+            path,
+            state: RwLock::new(KeyFile {
+                rotation_days: DEFAULT_ROTATION_DAYS,
+                grace_days: DEFAULT_GRACE_DAYS,
+                keys: Vec::new(),
+                token_hash_key: None,
+                encryption_key: None,
+            }),
+        };
+        store.ensure_install_keys().unwrap();
+        store.rotate_if_due().unwrap();
+        store
+    }
+}
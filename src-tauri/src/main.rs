@@ -2,21 +2,67 @@ mod database;
 mod models;
 mod commands;
 mod error;
+mod export;
+mod audit;
+mod avatars;
+mod keys;
+mod mail;
+mod maintenance;
+mod oidc;
+mod password_hashing;
+mod password_policy;
+mod preferences;
+mod privacy;
+mod profile;
+mod roles;
+mod session;
+mod throttle;
+mod totp;
 
+#[cfg(test)]
+mod test_support;
+
+use avatars::AvatarStore;
 use database::Database;
+use keys::KeyStore;
+use mail::MailQueue;
+use oidc::OidcClient;
 use commands::*;
 use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
+        .register_uri_scheme_protocol(avatars::PROTOCOL, |ctx, request| {
+            ctx.app_handle().state::<AvatarStore>().serve(&request)
+        })
         .setup(|app| {
+            // Load or generate the per-install JWT signing keys
+            let keys = KeyStore::new(app.handle()).expect("Failed to initialize signing keys");
+            app.manage(keys);
+
+            // Uploaded avatars live next to the database in the app data directory
+            let avatars = AvatarStore::new(app.handle()).expect("Failed to initialize avatar storage");
+            app.manage(avatars);
+
+            // Outbound mail is delivered by a background worker
+            let mail = MailQueue::new(app.handle()).expect("Failed to initialize mail transport");
+            app.manage(mail);
+
+            // Single sign-on stays disabled unless an identity provider is configured
+            let oidc = OidcClient::from_env().expect("Invalid single sign-on configuration");
+            app.manage(oidc);
+
             // Initialize database with app handle
             let handle = app.handle().clone();
             tauri::async_runtime::block_on(async move {
                 let db = Database::new(&handle).await.expect("Failed to initialize database");
                 app.manage(db);
+
+                // Expired tokens, stale rows and lapsed accounts are swept periodically
+                maintenance::start(handle);
             });
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -28,16 +74,60 @@ pub fn run() {
             forgot_password,
             reset_password,
             get_current_user,
+            change_password,
+            verify_email,
+            resend_verification_email,
+            check_password_strength,
+
+            // Two-factor commands
+            enroll_totp,
+            confirm_totp,
+            disable_totp,
+            verify_second_factor,
+
+            // Passwordless commands
+            request_login_code,
+            login_with_code,
+
+            // Single sign-on commands
+            begin_oidc_login,
+            complete_oidc_login,
+
+            // Audit commands
+            get_audit_events,
+
+            // Role commands
+            grant_role,
+            revoke_role,
+
+            // Session commands
+            list_sessions,
+            revoke_user_session,
+            revoke_other_sessions,
+            rotate_signing_key,
             
             // User commands
             get_user_profile,
             update_user_profile,
+            check_profile_update,
+            search_users,
+            upload_avatar,
             delete_user_account,
+            export_user_data,
+            
+            // Privacy commands
+            get_privacy_settings,
+            update_privacy_settings,
+            
+            // Preference commands
+            get_preferences,
+            update_preferences,
             
             // Progress commands
             add_progress,
             get_user_progress,
             get_user_progress_by_id,
+            get_progress_summary,
             update_progress,
             delete_progress,
             
@@ -61,7 +151,8 @@ pub fn run() {
             // Settings commands
             get_settings,
             update_settings,
-            get_metrics
+            get_metrics,
+            run_maintenance
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use tauri::{AppHandle, Manager};
+
+use crate::audit;
+use crate::avatars::AvatarStore;
+use crate::commands::users::purge_expired_accounts;
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::models::SweepReport;
+use crate::throttle::LoginThrottle;
+
+/// Runs `sweep` in the background every `maintenance_interval_minutes`.
+/// Must be started after `Database`, `KeyStore` and `AvatarStore` are managed.
+pub fn start(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let db = app_handle.state::<Database>();
+            let keys = app_handle.state::<KeyStore>();
+            let avatars = app_handle.state::<AvatarStore>();
+
+            // A failed sweep is simply retried next time; admins see the
+            // error when they run it on demand with `run_maintenance`
+            let _ = sweep(&db, &keys, &avatars).await;
+
+            let interval_minutes: u64 = db.get_setting("maintenance_interval_minutes").await
+                .ok()
+                .flatten()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60);
+            tokio::time::sleep(Duration::from_secs(interval_minutes.max(1) * 60)).await;
+        }
+    });
+}
+
+/// Deletes expired and stale rows according to the retention settings and
+/// reports how many rows each step removed.
+pub async fn sweep(db: &Database, keys: &KeyStore, avatars: &AvatarStore) -> Result<SweepReport, AppError> {
+    let retention_days = |key: &'static str, default: i64| async move {
+        let days: i64 = db.get_setting(key).await?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default);
+        Ok::<_, sqlx::Error>(format!("-{} days", days.max(1)))
+    };
+
+    // An expired refresh token can no longer be presented, so it is not
+    // needed for reuse detection either
+    let expired_refresh_tokens = execute(db, "DELETE FROM refresh_tokens WHERE expires_at <= datetime('now')", None).await?;
+
+    // Sessions left without any token have ended, whether revoked or expired
+    let ended_sessions = execute(
+        db,
+        "DELETE FROM refresh_token_families WHERE NOT EXISTS (SELECT 1 FROM refresh_tokens rt WHERE rt.family_id = refresh_token_families.id)",
+        None,
+    ).await?;
+
+    let password_reset_tokens = execute(db, "DELETE FROM password_reset_tokens WHERE used = 1 OR expires_at <= datetime('now')", None).await?;
+    let email_verification_tokens = execute(db, "DELETE FROM email_verification_tokens WHERE used = 1 OR expires_at <= datetime('now')", None).await?;
+    let login_codes = execute(db, "DELETE FROM login_codes WHERE used_at IS NOT NULL OR expires_at <= datetime('now')", None).await?;
+
+    // Enrollments never confirmed with a code are abandoned after a day
+    let pending_totp_enrollments = execute(db, "DELETE FROM user_totp WHERE confirmed_at IS NULL AND created_at <= datetime('now', '-1 day')", None).await?;
+
+    let read_notifications = execute(
+        db,
+        "DELETE FROM notifications WHERE is_read = 1 AND created_at < datetime('now', ?)",
+        Some(retention_days("notification_retention_days", 90).await?),
+    ).await?;
+
+    let login_attempts = LoginThrottle::prune_stale(db).await?;
+    let purged_accounts = purge_expired_accounts(db, keys, avatars).await?;
+    let audit_events = audit::prune(db).await?;
+
+    Ok(SweepReport {
+        expired_refresh_tokens,
+        ended_sessions,
+        password_reset_tokens,
+        email_verification_tokens,
+        login_codes,
+        pending_totp_enrollments,
+        read_notifications,
+        login_attempts,
+        purged_accounts,
+        audit_events,
+    })
+}
+
+async fn execute(db: &Database, query: &str, modifier: Option<String>) -> Result<u64, sqlx::Error> {
+    let mut query = sqlx::query(query);
+    if let Some(modifier) = modifier {
+        query = query.bind(modifier);
+    }
+
+    Ok(query.execute(db.get_pool()).await?.rows_affected())
+}
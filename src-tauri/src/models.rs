@@ -1,16 +1,24 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use chrono::{DateTime, Utc, NaiveDate};
 
+use std::collections::BTreeMap;
+
+use crate::preferences::{UnitSystem, WeekStart};
+use crate::privacy::Visibility;
+use crate::roles::Role;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub id: i64,
     pub email: String,
+    pub handle: Option<String>,
     pub first_name: String,
     pub last_name: String,
     pub avatar_url: Option<String>,
     pub goals: Vec<String>,
     pub is_active: bool,
     pub email_verified: bool,
+    pub role: Role,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -23,14 +31,40 @@ pub struct UserCreate {
     pub last_name: String,
 }
 
+/// Partial profile update; omitted fields stay unchanged.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserUpdate {
+    /// Omitted: unchanged; `null`: cleared; a string: claimed if free
+    #[serde(default, deserialize_with = "present_or_null", skip_serializing_if = "Option::is_none")]
+    pub handle: Option<Option<String>>,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
-    pub avatar_url: Option<String>,
+    /// Omitted: unchanged; `null`: cleared; a string: replaced
+    #[serde(default, deserialize_with = "present_or_null", skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<Option<String>>,
     pub goals: Option<Vec<String>>,
 }
 
+/// Keeps an explicit `null` apart from a missing field (which `default`
+/// turns into `None`).
+fn present_or_null<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// What other users see of an account in search results.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSummary {
+    pub id: i64,
+    pub handle: Option<String>,
+    pub first_name: String,
+    pub last_name: String,
+    pub avatar_url: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
@@ -44,6 +78,158 @@ pub struct AuthResponse {
     pub user: User,
 }
 
+/// Result of `login_user`: either a full session or, for accounts with
+/// two-factor authentication, a short-lived challenge to complete with
+/// `verify_second_factor`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LoginResponse {
+    Authenticated(Box<AuthResponse>),
+    SecondFactorRequired { challenge_token: String },
+}
+
+/// Describes the device a session is started from, as reported by the
+/// frontend (e.g. "Work laptop", "Windows 11 / Tauri 2.0").
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SessionClient {
+    pub device_label: Option<String>,
+    pub client_info: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActiveSession {
+    pub id: i64,
+    pub device_label: Option<String>,
+    pub client_info: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub is_current: bool,
+}
+
+/// Returned by `begin_oidc_login`: the frontend opens `authorization_url`
+/// in the system browser, then calls `complete_oidc_login` with `state`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcAuthorization {
+    pub authorization_url: String,
+    pub state: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub actor_user_id: Option<i64>,
+    pub event_type: String,
+    pub outcome: String,
+    pub metadata: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AuditEventFilter {
+    /// Defaults to the caller; other accounts need the audit log permission
+    pub actor_user_id: Option<i64>,
+    pub event_type: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEvent>,
+    pub total: i64,
+}
+
+/// Rows removed by one maintenance sweep, per kind.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SweepReport {
+    pub expired_refresh_tokens: u64,
+    pub ended_sessions: u64,
+    pub password_reset_tokens: u64,
+    pub email_verification_tokens: u64,
+    pub login_codes: u64,
+    pub pending_totp_enrollments: u64,
+    pub read_notifications: u64,
+    pub login_attempts: u64,
+    pub purged_accounts: u64,
+    pub audit_events: u64,
+}
+
+/// Who sees a user's progress. `categories` overrides `default_visibility`
+/// for the categories it names.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    pub default_visibility: Visibility,
+    pub categories: BTreeMap<String, Visibility>,
+    pub share_notes: bool,
+    pub show_on_leaderboard: bool,
+    pub discoverable: bool,
+}
+
+/// Partial privacy update; omitted fields stay unchanged and a category set
+/// to `null` goes back to the default visibility.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrivacySettingsUpdate {
+    pub default_visibility: Option<Visibility>,
+    pub categories: Option<BTreeMap<String, Option<Visibility>>>,
+    pub share_notes: Option<bool>,
+    pub show_on_leaderboard: Option<bool>,
+    pub discoverable: Option<bool>,
+}
+
+/// Per-user display and notification preferences, with global settings
+/// filled in for anything the user never chose.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPreferences {
+    pub unit_system: UnitSystem,
+    /// IANA name, e.g. "Europe/Paris"
+    pub timezone: String,
+    pub week_start: WeekStart,
+    pub email_notifications: bool,
+    pub progress_page_size: i64,
+}
+
+/// Partial preferences update. Omitted: unchanged; `null`: follow the
+/// global setting again; a value: replaced.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPreferencesUpdate {
+    #[serde(default, deserialize_with = "present_or_null", skip_serializing_if = "Option::is_none")]
+    pub unit_system: Option<Option<UnitSystem>>,
+    #[serde(default, deserialize_with = "present_or_null", skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present_or_null", skip_serializing_if = "Option::is_none")]
+    pub week_start: Option<Option<WeekStart>>,
+    #[serde(default, deserialize_with = "present_or_null", skip_serializing_if = "Option::is_none")]
+    pub email_notifications: Option<Option<bool>>,
+    #[serde(default, deserialize_with = "present_or_null", skip_serializing_if = "Option::is_none")]
+    pub progress_page_size: Option<Option<i64>>,
+}
+
+/// `manifest.json` of a personal data export.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub schema_version: u32,
+    pub app_version: String,
+    pub user_id: i64,
+    pub generated_at: DateTime<Utc>,
+    pub files: Vec<ExportedFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedFile {
+    pub path: String,
+    pub format: String,
+    pub rows: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Progress {
     pub id: i64,
@@ -58,6 +244,19 @@ pub struct Progress {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Progress entries of one category and metric within one period, in the
+/// viewer's unit system.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProgressBucket {
+    pub period_start: NaiveDate,
+    pub category: String,
+    pub metric: String,
+    pub unit: Option<String>,
+    pub entries: i64,
+    pub total: f64,
+    pub average: f64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProgressCreate {
     pub category: String,
@@ -0,0 +1,178 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier as _, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::rngs::OsRng;
+
+use crate::database::Database;
+use crate::error::AppError;
+
+/// Argon2id hashing with parameters from the `settings` table
+/// (`password_hash_*` keys). Hashes are stored as PHC strings in
+/// `users.password_hash`; bcrypt hashes from older installs still verify
+/// and are replaced on the next successful login (see `needs_rehash`).
+pub struct PasswordHasher {
+    params: Params,
+}
+
+impl PasswordHasher {
+    pub async fn load(db: &Database) -> Result<Self, sqlx::Error> {
+        let number = |value: Option<String>, default: u32| {
+            value.and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+
+        let memory_kib = number(db.get_setting("password_hash_memory_kib").await?, Params::DEFAULT_M_COST);
+        let iterations = number(db.get_setting("password_hash_iterations").await?, Params::DEFAULT_T_COST);
+        let parallelism = number(db.get_setting("password_hash_parallelism").await?, Params::DEFAULT_P_COST);
+
+        // Out-of-range settings must not lock everyone out of hashing
+        let params = Params::new(memory_kib, iterations, parallelism, None).unwrap_or_default();
+
+        Ok(PasswordHasher { params })
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, AppError> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| AppError::Internal(format!("Password hashing failed: {}", e)))
+    }
+
+    /// Checks a password against an Argon2 PHC string or a legacy bcrypt hash.
+    pub fn verify(&self, password: &str, stored_hash: &str) -> Result<bool, AppError> {
+        if is_bcrypt(stored_hash) {
+            return bcrypt::verify(password, stored_hash)
+                .map_err(|e| AppError::Internal(format!("Password verification failed: {}", e)));
+        }
+
+        let parsed = PasswordHash::new(stored_hash)
+            .map_err(|e| AppError::Internal(format!("Unrecognised password hash: {}", e)))?;
+
+        // Verification uses the parameters recorded in the hash itself
+        Ok(self.argon2().verify_password(password.as_bytes(), &parsed).is_ok())
+    }
+
+    /// Does the work of `verify` against a hash no password matches, for
+    /// sign-ins to unknown accounts: response times then do not reveal
+    /// which emails are registered.
+    pub fn verify_dummy(&self, password: &str) {
+        let dummy_hash = format!(
+            "$argon2id$v=19$m={},t={},p={}${}${}",
+            self.params.m_cost(),
+            self.params.t_cost(),
+            self.params.p_cost(),
+            "A".repeat(22),
+            "A".repeat(43),
+        );
+        let _ = self.verify(password, &dummy_hash);
+    }
+
+    /// True when a stored hash is not Argon2id or was made with weaker
+    /// parameters than currently configured.
+    pub fn needs_rehash(&self, stored_hash: &str) -> bool {
+        let parsed = match PasswordHash::new(stored_hash) {
+            Ok(parsed) if parsed.algorithm == Algorithm::Argon2id.ident() => parsed,
+            _ => return true,
+        };
+
+        if parsed.version != Some(Version::V0x13.into()) {
+            return true;
+        }
+
+        match Params::try_from(&parsed) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+fn is_bcrypt(stored_hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| stored_hash.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters keep the tests fast; the logic does not depend on them
+    fn hasher(memory_kib: u32, iterations: u32) -> PasswordHasher {
+        PasswordHasher { params: Params::new(memory_kib, iterations, 1, None).unwrap() }
+    }
+
+    #[test]
+    fn hashes_with_argon2id_and_verifies() {
+        let hasher = hasher(1024, 1);
+        let hash = hasher.hash("correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(hasher.verify("correct horse", &hash).unwrap());
+        assert!(!hasher.verify("wrong horse", &hash).unwrap());
+        assert!(!hasher.needs_rehash(&hash));
+    }
+
+    #[test]
+    fn salts_every_hash() {
+        let hasher = hasher(1024, 1);
+        assert_ne!(hasher.hash("correct horse").unwrap(), hasher.hash("correct horse").unwrap());
+    }
+
+    #[test]
+    fn verifies_legacy_bcrypt_and_asks_for_a_rehash() {
+        let hasher = hasher(1024, 1);
+        let legacy = bcrypt::hash("correct horse", 4).unwrap();
+
+        assert!(hasher.verify("correct horse", &legacy).unwrap());
+        assert!(!hasher.verify("wrong horse", &legacy).unwrap());
+        assert!(hasher.needs_rehash(&legacy));
+
+        // What login stores after a successful bcrypt verification
+        let upgraded = hasher.hash("correct horse").unwrap();
+        assert!(hasher.verify("correct horse", &upgraded).unwrap());
+        assert!(!hasher.needs_rehash(&upgraded));
+    }
+
+    #[test]
+    fn asks_for_a_rehash_when_parameters_grow() {
+        let old = hasher(1024, 1).hash("correct horse").unwrap();
+
+        assert!(hasher(2048, 1).needs_rehash(&old));
+        assert!(hasher(1024, 2).needs_rehash(&old));
+        // Stronger stored parameters are left alone
+        assert!(!hasher(512, 1).needs_rehash(&old));
+        assert!(hasher(512, 1).verify("correct horse", &old).unwrap());
+    }
+
+    #[test]
+    fn asks_for_a_rehash_of_other_argon2_variants() {
+        let argon2i = Argon2::new(Algorithm::Argon2i, Version::V0x13, Params::new(1024, 1, 1, None).unwrap())
+            .hash_password(b"correct horse", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+
+        assert!(hasher(1024, 1).needs_rehash(&argon2i));
+    }
+
+    #[test]
+    fn rejects_unrecognised_hashes() {
+        assert!(hasher(1024, 1).verify("correct horse", "plaintext").is_err());
+    }
+
+    #[test]
+    fn dummy_verification_does_the_same_work() {
+        let hasher = hasher(1024, 1);
+        let dummy = format!("$argon2id$v=19$m=1024,t=1,p=1${}${}", "A".repeat(22), "A".repeat(43));
+
+        // The dummy hash must parse, or the unknown-email path would skip hashing
+        assert!(PasswordHash::new(&dummy).is_ok());
+        assert!(!hasher.verify("correct horse", &dummy).unwrap());
+        hasher.verify_dummy("correct horse");
+    }
+}
@@ -0,0 +1,205 @@
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::error::AppError;
+
+// Lower-cased, one password per line
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// Password rules read from the `settings` table (`password_*` keys).
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_bytes: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub reject_personal_info: bool,
+    pub reject_common: bool,
+}
+
+/// Account details a password must not contain.
+#[derive(Debug, Default)]
+pub struct PersonalInfo<'a> {
+    pub email: &'a str,
+    pub first_name: &'a str,
+    pub last_name: &'a str,
+}
+
+/// One failed rule, with a stable code the frontend can map to UI hints.
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordViolation {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            // Bounds hashing work; also what legacy bcrypt hashes could verify
+            max_bytes: 72,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+            reject_personal_info: true,
+            reject_common: true,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub async fn load(db: &Database) -> Result<Self, sqlx::Error> {
+        let defaults = PasswordPolicy::default();
+
+        let number = |value: Option<String>, default: usize| {
+            value.and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+
+        Ok(PasswordPolicy {
+            min_length: number(db.get_setting("password_min_length").await?, defaults.min_length),
+            max_bytes: number(db.get_setting("password_max_bytes").await?, defaults.max_bytes),
+            require_lowercase: db.setting_enabled("password_require_lowercase", defaults.require_lowercase).await?,
+            require_uppercase: db.setting_enabled("password_require_uppercase", defaults.require_uppercase).await?,
+            require_digit: db.setting_enabled("password_require_digit", defaults.require_digit).await?,
+            require_symbol: db.setting_enabled("password_require_symbol", defaults.require_symbol).await?,
+            reject_personal_info: db.setting_enabled("password_reject_personal_info", defaults.reject_personal_info).await?,
+            reject_common: db.setting_enabled("password_reject_common", defaults.reject_common).await?,
+        })
+    }
+
+    /// Returns every rule the password breaks; empty means acceptable.
+    pub fn check(&self, password: &str, personal: &PersonalInfo) -> Vec<PasswordViolation> {
+        let mut violations = Vec::new();
+        let mut violate = |code: &'static str, message: String| {
+            violations.push(PasswordViolation { code, message });
+        };
+
+        if password.chars().count() < self.min_length {
+            violate("too_short", format!("Password must be at least {} characters", self.min_length));
+        }
+        if password.len() > self.max_bytes {
+            violate("too_long", format!("Password must be at most {} bytes", self.max_bytes));
+        }
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            violate("missing_lowercase", "Password must contain a lowercase letter".to_string());
+        }
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            violate("missing_uppercase", "Password must contain an uppercase letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violate("missing_digit", "Password must contain a digit".to_string());
+        }
+        if self.require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+            violate("missing_symbol", "Password must contain a symbol".to_string());
+        }
+
+        let lowered = password.to_lowercase();
+
+        if self.reject_personal_info {
+            let email_name = personal.email.split('@').next().unwrap_or_default();
+            let contains_personal = [email_name, personal.first_name, personal.last_name]
+                .iter()
+                .map(|part| part.trim().to_lowercase())
+                // Very short names would match too many unrelated passwords
+                .filter(|part| part.chars().count() >= 3)
+                .any(|part| lowered.contains(&part));
+
+            if contains_personal {
+                violate("contains_personal_info", "Password must not contain your name or email".to_string());
+            }
+        }
+
+        if self.reject_common && COMMON_PASSWORDS.lines().any(|common| common == lowered) {
+            violate("too_common", "Password is too common".to_string());
+        }
+
+        violations
+    }
+
+    /// Like `check`, folded into a single validation error for commands.
+    pub fn enforce(&self, password: &str, personal: &PersonalInfo) -> Result<(), AppError> {
+        let violations = self.check(password, personal);
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let messages: Vec<String> = violations.into_iter().map(|v| v.message).collect();
+        Err(AppError::Validation(messages.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JANE: PersonalInfo<'static> = PersonalInfo {
+        email: "jane.doe@example.com",
+        first_name: "Jane",
+        last_name: "Doe",
+    };
+
+    fn codes(policy: &PasswordPolicy, password: &str) -> Vec<&'static str> {
+        policy.check(password, &JANE).into_iter().map(|v| v.code).collect()
+    }
+
+    #[test]
+    fn accepts_a_password_meeting_every_rule() {
+        assert!(codes(&PasswordPolicy::default(), "Tr0ub4dor&3xyz").is_empty());
+    }
+
+    #[test]
+    fn reports_every_broken_rule_at_once() {
+        assert_eq!(codes(&PasswordPolicy::default(), "abc"), ["too_short", "missing_uppercase", "missing_digit"]);
+        assert_eq!(codes(&PasswordPolicy::default(), "ABCDEFGH1"), ["missing_lowercase"]);
+    }
+
+    #[test]
+    fn limits_length_in_bytes() {
+        let password = format!("Aa1{}", "é".repeat(40));
+        assert_eq!(codes(&PasswordPolicy::default(), &password), ["too_long"]);
+    }
+
+    #[test]
+    fn requires_a_symbol_only_when_configured() {
+        let policy = PasswordPolicy { require_symbol: true, ..PasswordPolicy::default() };
+        assert_eq!(codes(&policy, "Tr0ub4dorxyz"), ["missing_symbol"]);
+        assert!(codes(&policy, "Tr0ub4dor&xyz").is_empty());
+    }
+
+    #[test]
+    fn rejects_names_and_email_ignoring_case() {
+        assert_eq!(codes(&PasswordPolicy::default(), "MyNameIsJANE1"), ["contains_personal_info"]);
+        assert_eq!(codes(&PasswordPolicy::default(), "Xx9jane.doeXx"), ["contains_personal_info"]);
+
+        let policy = PasswordPolicy { reject_personal_info: false, ..PasswordPolicy::default() };
+        assert!(codes(&policy, "MyNameIsJANE1").is_empty());
+    }
+
+    #[test]
+    fn ignores_very_short_names() {
+        let personal = PersonalInfo { email: "al@example.com", first_name: "Al", last_name: "Li" };
+        assert!(PasswordPolicy::default().check("CalmLily42x", &personal).is_empty());
+    }
+
+    #[test]
+    fn rejects_common_passwords() {
+        let policy = PasswordPolicy {
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            ..PasswordPolicy::default()
+        };
+        assert_eq!(codes(&policy, "Password"), ["too_common"]);
+    }
+
+    #[test]
+    fn enforce_joins_messages_into_one_validation_error() {
+        match PasswordPolicy::default().enforce("abc", &JANE) {
+            Err(AppError::Validation(message)) => assert_eq!(message.matches("; ").count(), 2),
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+}
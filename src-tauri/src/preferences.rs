@@ -0,0 +1,322 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::error::AppError;
+use crate::models::{UserPreferences, UserPreferencesUpdate};
+
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Units values are shown in where a conversion is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum UnitSystem {
+    Metric,
+    Imperial,
+}
+
+/// First day of a week when progress is grouped by week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum WeekStart {
+    Monday,
+    Saturday,
+    Sunday,
+}
+
+/// Length of the buckets progress summaries are grouped into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+// Metric unit, imperial unit, imperial units per metric unit
+const CONVERSIONS: &[(&str, &str, f64)] = &[
+    ("kg", "lb", 2.204_622_621_8),
+    ("g", "oz", 0.035_273_961_9),
+    ("km", "mi", 0.621_371_192_2),
+    ("m", "ft", 3.280_839_895),
+    ("cm", "in", 0.393_700_787_4),
+    ("l", "gal", 0.264_172_052_4),
+];
+
+impl UnitSystem {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "metric" => Some(UnitSystem::Metric),
+            "imperial" => Some(UnitSystem::Imperial),
+            _ => None,
+        }
+    }
+
+    /// Expresses a value in this system when its unit has a known
+    /// counterpart; anything else is returned unchanged.
+    pub fn convert(&self, value: f64, unit: Option<&str>) -> (f64, Option<String>) {
+        let Some(unit) = unit else {
+            return (value, None);
+        };
+        let lower = unit.trim().to_lowercase();
+
+        for (metric, imperial, factor) in CONVERSIONS {
+            match self {
+                UnitSystem::Imperial if lower == *metric => return (value * factor, Some(imperial.to_string())),
+                UnitSystem::Metric if lower == *imperial => return (value / factor, Some(metric.to_string())),
+                _ => {}
+            }
+        }
+
+        (value, Some(unit.to_string()))
+    }
+}
+
+impl WeekStart {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "monday" => Some(WeekStart::Monday),
+            "saturday" => Some(WeekStart::Saturday),
+            "sunday" => Some(WeekStart::Sunday),
+            _ => None,
+        }
+    }
+
+    fn weekday(&self) -> chrono::Weekday {
+        match self {
+            WeekStart::Monday => chrono::Weekday::Mon,
+            WeekStart::Saturday => chrono::Weekday::Sat,
+            WeekStart::Sunday => chrono::Weekday::Sun,
+        }
+    }
+}
+
+impl UserPreferences {
+    /// The user's current date, which decides where "this week" ends.
+    pub fn today(&self) -> NaiveDate {
+        let timezone = Tz::from_str(&self.timezone).unwrap_or(Tz::UTC);
+        Utc::now().with_timezone(&timezone).date_naive()
+    }
+
+    /// First day of the bucket `date` falls in.
+    pub fn period_start(&self, period: SummaryPeriod, date: NaiveDate) -> NaiveDate {
+        match period {
+            SummaryPeriod::Day => date,
+            SummaryPeriod::Week => date.week(self.week_start.weekday()).first_day(),
+            SummaryPeriod::Month => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    /// First day of the oldest of `count` buckets ending with today's.
+    pub fn window_start(&self, period: SummaryPeriod, count: u32) -> NaiveDate {
+        let current = self.period_start(period, self.today());
+        let back = count.saturating_sub(1);
+        match period {
+            SummaryPeriod::Day => current - chrono::Days::new(back.into()),
+            SummaryPeriod::Week => current - chrono::Days::new(u64::from(back) * 7),
+            SummaryPeriod::Month => current.checked_sub_months(Months::new(back)).unwrap_or(current),
+        }
+    }
+}
+
+/// A user's preferences, with anything they never set taken from the
+/// matching global setting.
+pub async fn load(db: &Database, user_id: i64) -> Result<UserPreferences, AppError> {
+    let defaults = defaults(db).await?;
+
+    let row = sqlx::query(
+        "SELECT unit_system, timezone, week_start, email_notifications, progress_page_size FROM user_preferences WHERE user_id = ?"
+    )
+    .bind(user_id)
+    .fetch_optional(db.get_pool())
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(defaults);
+    };
+
+    Ok(UserPreferences {
+        unit_system: row.get::<Option<UnitSystem>, _>("unit_system").unwrap_or(defaults.unit_system),
+        timezone: row.get::<Option<String>, _>("timezone").unwrap_or(defaults.timezone),
+        week_start: row.get::<Option<WeekStart>, _>("week_start").unwrap_or(defaults.week_start),
+        email_notifications: row.get::<Option<bool>, _>("email_notifications").unwrap_or(defaults.email_notifications),
+        progress_page_size: row.get::<Option<i64>, _>("progress_page_size").unwrap_or(defaults.progress_page_size),
+    })
+}
+
+/// Applies a partial update; a field set to `null` goes back to following
+/// the global setting.
+pub async fn update(db: &Database, user_id: i64, update: &UserPreferencesUpdate) -> Result<(), AppError> {
+    if let Some(Some(timezone)) = &update.timezone {
+        if Tz::from_str(timezone).is_err() {
+            return Err(AppError::Validation(format!("Unknown time zone: {}", timezone)));
+        }
+    }
+    if let Some(Some(page_size)) = update.progress_page_size {
+        if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(AppError::Validation(format!("Page size must be between 1 and {}", MAX_PAGE_SIZE)));
+        }
+    }
+
+    let mut tx = db.get_pool().begin().await?;
+
+    sqlx::query("INSERT INTO user_preferences (user_id) VALUES (?) ON CONFLICT(user_id) DO NOTHING")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE user_preferences SET
+            unit_system = CASE WHEN ? THEN ? ELSE unit_system END,
+            timezone = CASE WHEN ? THEN ? ELSE timezone END,
+            week_start = CASE WHEN ? THEN ? ELSE week_start END,
+            email_notifications = CASE WHEN ? THEN ? ELSE email_notifications END,
+            progress_page_size = CASE WHEN ? THEN ? ELSE progress_page_size END,
+            updated_at = datetime('now')
+        WHERE user_id = ?
+        "#
+    )
+    .bind(update.unit_system.is_some())
+    .bind(update.unit_system.flatten())
+    .bind(update.timezone.is_some())
+    .bind(update.timezone.clone().flatten())
+    .bind(update.week_start.is_some())
+    .bind(update.week_start.flatten())
+    .bind(update.email_notifications.is_some())
+    .bind(update.email_notifications.flatten())
+    .bind(update.progress_page_size.is_some())
+    .bind(update.progress_page_size.flatten())
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn defaults(db: &Database) -> Result<UserPreferences, AppError> {
+    let setting = |key: &'static str| db.get_setting(key);
+
+    Ok(UserPreferences {
+        unit_system: setting("default_unit_system").await?
+            .and_then(|value| UnitSystem::parse(&value))
+            .unwrap_or(UnitSystem::Metric),
+        timezone: setting("default_timezone").await?
+            .filter(|value| Tz::from_str(value).is_ok())
+            .unwrap_or_else(|| "UTC".to_string()),
+        week_start: setting("default_week_start").await?
+            .and_then(|value| WeekStart::parse(&value))
+            .unwrap_or(WeekStart::Monday),
+        email_notifications: db.setting_enabled("email_notifications_enabled", true).await?,
+        progress_page_size: setting("max_progress_display").await?
+            .and_then(|value| value.parse().ok())
+            .filter(|size| (1..=MAX_PAGE_SIZE).contains(size))
+            .unwrap_or(100),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn set_setting(db: &Database, key: &str, value: &str) {
+        sqlx::query("UPDATE settings SET value = ? WHERE key = ?")
+            .bind(value)
+            .bind(key)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+    }
+
+    fn parse_update(json: &str) -> UserPreferencesUpdate {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn unset_preferences_follow_the_global_settings() {
+        let db = Database::in_memory().await.unwrap();
+
+        let preferences = load(&db, 1).await.unwrap();
+        assert_eq!(preferences.unit_system, UnitSystem::Metric);
+        assert_eq!(preferences.timezone, "UTC");
+        assert_eq!(preferences.week_start, WeekStart::Monday);
+        assert!(preferences.email_notifications);
+        assert_eq!(preferences.progress_page_size, 100);
+
+        set_setting(&db, "default_unit_system", "imperial").await;
+        set_setting(&db, "max_progress_display", "25").await;
+        // Unusable settings fall back rather than fail
+        set_setting(&db, "default_timezone", "Mars/Olympus").await;
+
+        let preferences = load(&db, 1).await.unwrap();
+        assert_eq!(preferences.unit_system, UnitSystem::Imperial);
+        assert_eq!(preferences.progress_page_size, 25);
+        assert_eq!(preferences.timezone, "UTC");
+    }
+
+    #[tokio::test]
+    async fn chosen_preferences_override_and_null_restores_the_default() {
+        let db = Database::in_memory().await.unwrap();
+        sqlx::query("INSERT INTO users (email, password_hash, first_name, last_name) VALUES ('jane@example.com', 'x', 'Jane', 'Doe')")
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        update(&db, 1, &parse_update(r#"{"timezone": "Europe/Paris", "week_start": "sunday", "progress_page_size": 20}"#)).await.unwrap();
+        // Fields left out keep their value
+        update(&db, 1, &parse_update(r#"{"progress_page_size": null}"#)).await.unwrap();
+
+        let preferences = load(&db, 1).await.unwrap();
+        assert_eq!(preferences.timezone, "Europe/Paris");
+        assert_eq!(preferences.week_start, WeekStart::Sunday);
+        assert_eq!(preferences.progress_page_size, 100);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_time_zones_and_page_sizes_out_of_range() {
+        let db = Database::in_memory().await.unwrap();
+
+        for json in [r#"{"timezone": "Mars/Olympus"}"#, r#"{"progress_page_size": 0}"#, r#"{"progress_page_size": 501}"#] {
+            assert!(matches!(update(&db, 1, &parse_update(json)).await, Err(AppError::Validation(_))), "{}", json);
+        }
+    }
+
+    #[test]
+    fn converts_only_known_units() {
+        let (value, unit) = UnitSystem::Imperial.convert(10.0, Some("KG"));
+        assert!((value - 22.046).abs() < 0.001);
+        assert_eq!(unit.as_deref(), Some("lb"));
+
+        let (value, unit) = UnitSystem::Metric.convert(1.0, Some("mi"));
+        assert!((value - 1.609).abs() < 0.001);
+        assert_eq!(unit.as_deref(), Some("km"));
+
+        assert_eq!(UnitSystem::Imperial.convert(3.0, Some("reps")), (3.0, Some("reps".to_string())));
+        assert_eq!(UnitSystem::Metric.convert(3.0, None), (3.0, None));
+    }
+
+    #[test]
+    fn buckets_weeks_by_the_chosen_week_start() {
+        let preferences = |week_start| UserPreferences {
+            unit_system: UnitSystem::Metric,
+            timezone: "UTC".to_string(),
+            week_start,
+            email_notifications: true,
+            progress_page_size: 100,
+        };
+        // A Wednesday
+        let date = NaiveDate::from_ymd_opt(2024, 5, 15).unwrap();
+
+        assert_eq!(preferences(WeekStart::Monday).period_start(SummaryPeriod::Week, date), NaiveDate::from_ymd_opt(2024, 5, 13).unwrap());
+        assert_eq!(preferences(WeekStart::Sunday).period_start(SummaryPeriod::Week, date), NaiveDate::from_ymd_opt(2024, 5, 12).unwrap());
+        assert_eq!(preferences(WeekStart::Saturday).period_start(SummaryPeriod::Month, date), NaiveDate::from_ymd_opt(2024, 5, 1).unwrap());
+    }
+}
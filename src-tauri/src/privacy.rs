@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::error::AppError;
+use crate::models::{PrivacySettings, PrivacySettingsUpdate};
+
+/// Who can see progress in a category. Stored as its snake_case name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Visibility {
+    Private,
+    FriendsOnly,
+    Public,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Private => "private",
+            Visibility::FriendsOnly => "friends_only",
+            Visibility::Public => "public",
+        }
+    }
+}
+
+/// Applies until a user picks their own default.
+pub const DEFAULT_VISIBILITY: Visibility = Visibility::FriendsOnly;
+
+/// SQL expression for the visibility of the progress row aliased `p`: its
+/// category override, else the owner's default, else `DEFAULT_VISIBILITY`.
+fn entry_visibility_sql() -> String {
+    format!(
+        "COALESCE(\
+        (SELECT c.visibility FROM privacy_category_visibility c WHERE c.user_id = p.user_id AND c.category = p.category), \
+        (SELECT s.default_visibility FROM privacy_settings s WHERE s.user_id = p.user_id), \
+        '{}')",
+        DEFAULT_VISIBILITY.as_str()
+    )
+}
+
+/// SQL condition on the user row aliased `u`: false once they opted out of leaderboards.
+pub const ON_LEADERBOARD_SQL: &str =
+    "COALESCE((SELECT s.show_on_leaderboard FROM privacy_settings s WHERE s.user_id = u.id), 1) = 1";
+
+/// SQL condition on the user row aliased `u`: false once they asked not to
+/// be found by partial handle or name.
+pub const DISCOVERABLE_SQL: &str =
+    "COALESCE((SELECT s.discoverable FROM privacy_settings s WHERE s.user_id = u.id), 1) = 1";
+
+const MAX_CATEGORY_LENGTH: usize = 100;
+
+/// How a viewer relates to the owner of some progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Audience {
+    Owner,
+    Friend,
+    Anyone,
+}
+
+impl Audience {
+    /// Someone counts as a friend once the owner invited them or accepted
+    /// their invitation; asking to be friends grants nothing by itself.
+    pub async fn of(db: &Database, owner_id: i64, viewer_id: i64) -> Result<Self, AppError> {
+        if owner_id == viewer_id {
+            return Ok(Audience::Owner);
+        }
+
+        let friendship = sqlx::query(
+            r#"
+            SELECT 1 FROM user_friends
+            WHERE (user_id = ? AND friend_id = ?)
+               OR (user_id = ? AND friend_id = ? AND status = 'accepted')
+            LIMIT 1
+            "#
+        )
+        .bind(owner_id)
+        .bind(viewer_id)
+        .bind(viewer_id)
+        .bind(owner_id)
+        .fetch_optional(db.get_pool())
+        .await?;
+
+        Ok(if friendship.is_some() { Audience::Friend } else { Audience::Anyone })
+    }
+
+    /// Condition to append to a progress query over the `p` alias.
+    pub fn progress_filter(&self) -> String {
+        match self {
+            Audience::Owner => String::new(),
+            Audience::Friend => format!(" AND {} IN ('friends_only', 'public')", entry_visibility_sql()),
+            Audience::Anyone => format!(" AND {} = 'public'", entry_visibility_sql()),
+        }
+    }
+
+    /// Whether this audience gets `notes` on the entries it can see.
+    pub async fn sees_notes(&self, db: &Database, owner_id: i64) -> Result<bool, AppError> {
+        if *self == Audience::Owner {
+            return Ok(true);
+        }
+
+        let row = sqlx::query("SELECT share_notes FROM privacy_settings WHERE user_id = ?")
+            .bind(owner_id)
+            .fetch_optional(db.get_pool())
+            .await?;
+
+        Ok(row.is_some_and(|row| row.get::<bool, _>("share_notes")))
+    }
+}
+
+pub async fn load(db: &Database, user_id: i64) -> Result<PrivacySettings, AppError> {
+    let row = sqlx::query("SELECT default_visibility, share_notes, show_on_leaderboard, discoverable FROM privacy_settings WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(db.get_pool())
+        .await?;
+
+    let categories = sqlx::query("SELECT category, visibility FROM privacy_category_visibility WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_all(db.get_pool())
+        .await?
+        .into_iter()
+        .map(|row| (row.get("category"), row.get("visibility")))
+        .collect::<BTreeMap<String, Visibility>>();
+
+    Ok(match row {
+        Some(row) => PrivacySettings {
+            default_visibility: row.get("default_visibility"),
+            categories,
+            share_notes: row.get("share_notes"),
+            show_on_leaderboard: row.get("show_on_leaderboard"),
+            discoverable: row.get("discoverable"),
+        },
+        None => PrivacySettings {
+            default_visibility: DEFAULT_VISIBILITY,
+            categories,
+            share_notes: false,
+            show_on_leaderboard: true,
+            discoverable: true,
+        },
+    })
+}
+
+/// Applies a partial update in one transaction.
+pub async fn update(db: &Database, user_id: i64, update: &PrivacySettingsUpdate) -> Result<(), AppError> {
+    let categories = update.categories.as_ref()
+        .map(|categories| {
+            categories.iter()
+                .map(|(category, visibility)| {
+                    let category = category.trim();
+                    if category.is_empty() || category.chars().count() > MAX_CATEGORY_LENGTH {
+                        return Err(AppError::Validation(format!(
+                            "Category names must be 1 to {} characters",
+                            MAX_CATEGORY_LENGTH
+                        )));
+                    }
+                    Ok((category.to_string(), *visibility))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut tx = db.get_pool().begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO privacy_settings (user_id, default_visibility, share_notes, show_on_leaderboard, discoverable)
+        VALUES (?, COALESCE(?, ?), COALESCE(?, 0), COALESCE(?, 1), COALESCE(?, 1))
+        ON CONFLICT(user_id) DO UPDATE SET
+            default_visibility = COALESCE(?, default_visibility),
+            share_notes = COALESCE(?, share_notes),
+            show_on_leaderboard = COALESCE(?, show_on_leaderboard),
+            discoverable = COALESCE(?, discoverable),
+            updated_at = datetime('now')
+        "#
+    )
+    .bind(user_id)
+    .bind(update.default_visibility)
+    .bind(DEFAULT_VISIBILITY)
+    .bind(update.share_notes)
+    .bind(update.show_on_leaderboard)
+    .bind(update.discoverable)
+    .bind(update.default_visibility)
+    .bind(update.share_notes)
+    .bind(update.show_on_leaderboard)
+    .bind(update.discoverable)
+    .execute(&mut *tx)
+    .await?;
+
+    for (category, visibility) in &categories {
+        match visibility {
+            Some(visibility) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO privacy_category_visibility (user_id, category, visibility) VALUES (?, ?, ?)
+                    ON CONFLICT(user_id, category) DO UPDATE SET visibility = excluded.visibility
+                    "#
+                )
+                .bind(user_id)
+                .bind(category)
+                .bind(visibility)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM privacy_category_visibility WHERE user_id = ? AND category = ?")
+                    .bind(user_id)
+                    .bind(category)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn create_user(db: &Database, email: &str) -> i64 {
+        sqlx::query("INSERT INTO users (email, password_hash, first_name, last_name) VALUES (?, 'x', 'Jane', 'Doe')")
+            .bind(email)
+            .execute(db.get_pool())
+            .await
+            .unwrap()
+            .last_insert_rowid()
+    }
+
+    async fn add_progress(db: &Database, user_id: i64, category: &str) {
+        sqlx::query("INSERT INTO progress (user_id, category, metric, value, date) VALUES (?, ?, 'minutes', 1, '2024-01-01')")
+            .bind(user_id)
+            .bind(category)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+    }
+
+    async fn visible_categories(db: &Database, owner_id: i64, audience: Audience) -> Vec<String> {
+        sqlx::query(&format!("SELECT p.category FROM progress p WHERE p.user_id = ?{} ORDER BY p.category", audience.progress_filter()))
+            .bind(owner_id)
+            .fetch_all(db.get_pool())
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("category"))
+            .collect()
+    }
+
+    fn categories(entries: &[(&str, Option<Visibility>)]) -> PrivacySettingsUpdate {
+        PrivacySettingsUpdate {
+            default_visibility: None,
+            categories: Some(entries.iter().map(|(category, visibility)| (category.to_string(), *visibility)).collect()),
+            share_notes: None,
+            show_on_leaderboard: None,
+            discoverable: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_invitations_the_owner_made_or_accepted_make_a_friend() {
+        let db = Database::in_memory().await.unwrap();
+        let owner = create_user(&db, "owner@example.com").await;
+        let invited = create_user(&db, "invited@example.com").await;
+        let asking = create_user(&db, "asking@example.com").await;
+
+        sqlx::query("INSERT INTO user_friends (user_id, friend_id) VALUES (?, ?), (?, ?)")
+            .bind(owner)
+            .bind(invited)
+            .bind(asking)
+            .bind(owner)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        assert_eq!(Audience::of(&db, owner, owner).await.unwrap(), Audience::Owner);
+        assert_eq!(Audience::of(&db, owner, invited).await.unwrap(), Audience::Friend);
+        assert_eq!(Audience::of(&db, owner, asking).await.unwrap(), Audience::Anyone);
+
+        sqlx::query("UPDATE user_friends SET status = 'accepted' WHERE user_id = ?")
+            .bind(asking)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!(Audience::of(&db, owner, asking).await.unwrap(), Audience::Friend);
+    }
+
+    #[tokio::test]
+    async fn progress_is_filtered_by_category_then_default_visibility() {
+        let db = Database::in_memory().await.unwrap();
+        let owner = create_user(&db, "owner@example.com").await;
+        for category in ["fitness", "journal", "reading"] {
+            add_progress(&db, owner, category).await;
+        }
+
+        // Without any settings, DEFAULT_VISIBILITY applies
+        assert_eq!(visible_categories(&db, owner, Audience::Friend).await, ["fitness", "journal", "reading"]);
+        assert!(visible_categories(&db, owner, Audience::Anyone).await.is_empty());
+
+        update(&db, owner, &categories(&[("fitness", Some(Visibility::Public)), ("journal", Some(Visibility::Private))])).await.unwrap();
+        assert_eq!(visible_categories(&db, owner, Audience::Owner).await, ["fitness", "journal", "reading"]);
+        assert_eq!(visible_categories(&db, owner, Audience::Friend).await, ["fitness", "reading"]);
+        assert_eq!(visible_categories(&db, owner, Audience::Anyone).await, ["fitness"]);
+
+        // Clearing an override falls back to the default
+        update(&db, owner, &categories(&[("journal", None)])).await.unwrap();
+        assert_eq!(visible_categories(&db, owner, Audience::Friend).await, ["fitness", "journal", "reading"]);
+    }
+
+    #[tokio::test]
+    async fn notes_are_shared_only_when_the_owner_opted_in() {
+        let db = Database::in_memory().await.unwrap();
+        let owner = create_user(&db, "owner@example.com").await;
+
+        assert!(Audience::Owner.sees_notes(&db, owner).await.unwrap());
+        assert!(!Audience::Friend.sees_notes(&db, owner).await.unwrap());
+
+        let mut share = categories(&[]);
+        share.share_notes = Some(true);
+        update(&db, owner, &share).await.unwrap();
+        assert!(Audience::Friend.sees_notes(&db, owner).await.unwrap());
+        assert_eq!(load(&db, owner).await.unwrap().default_visibility, DEFAULT_VISIBILITY);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_category_names() {
+        let db = Database::in_memory().await.unwrap();
+        let owner = create_user(&db, "owner@example.com").await;
+
+        let long_name = "x".repeat(MAX_CATEGORY_LENGTH + 1);
+        for name in ["  ", long_name.as_str()] {
+            let result = update(&db, owner, &categories(&[(name, Some(Visibility::Public))])).await;
+            assert!(matches!(result, Err(AppError::Validation(_))), "{:?}", name);
+        }
+    }
+}
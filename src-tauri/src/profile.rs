@@ -0,0 +1,228 @@
+use serde::Serialize;
+use url::Url;
+
+use crate::avatars::is_stored_avatar;
+use crate::error::AppError;
+use crate::models::UserUpdate;
+
+const MAX_NAME_CHARS: usize = 50;
+const MAX_AVATAR_URL_BYTES: usize = 2048;
+const MAX_GOALS: usize = 20;
+const MAX_GOAL_CHARS: usize = 100;
+const MIN_HANDLE_CHARS: usize = 3;
+const MAX_HANDLE_CHARS: usize = 30;
+/// Handles that could pass for the app or its staff, or clash with routes
+const RESERVED_HANDLES: &[&str] = &[
+    "admin", "administrator", "api", "help", "me", "mod", "moderator", "null", "official",
+    "progress2win", "root", "security", "settings", "staff", "support", "system", "undefined",
+];
+
+/// One invalid field of a profile update, with a stable code the frontend
+/// can map to a message next to that field.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// A validated and normalised `UserUpdate`. `None` leaves a column
+/// unchanged; `handle` or `avatar_url` set to `Some(None)` clears it.
+#[derive(Debug, Default)]
+pub struct ProfileChanges {
+    pub handle: Option<Option<String>>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub avatar_url: Option<Option<String>>,
+    pub goals: Option<Vec<String>>,
+}
+
+impl ProfileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.handle.is_none() && self.first_name.is_none() && self.last_name.is_none() && self.avatar_url.is_none() && self.goals.is_none()
+    }
+
+    /// Names of the fields this update touches.
+    pub fn fields(&self) -> Vec<&'static str> {
+        [
+            ("handle", self.handle.is_some()),
+            ("first_name", self.first_name.is_some()),
+            ("last_name", self.last_name.is_some()),
+            ("avatar_url", self.avatar_url.is_some()),
+            ("goals", self.goals.is_some()),
+        ].iter().filter(|(_, changed)| *changed).map(|(field, _)| *field).collect()
+    }
+}
+
+/// Returns every problem with the update; empty means it can be applied.
+pub fn check(update: &UserUpdate) -> Vec<FieldError> {
+    match normalize(update) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors,
+    }
+}
+
+/// Like `check`, folded into a single validation error for commands.
+pub fn validate(update: &UserUpdate) -> Result<ProfileChanges, AppError> {
+    normalize(update).map_err(|errors| {
+        let messages: Vec<String> = errors.into_iter().map(|e| format!("{}: {}", e.field, e.message)).collect();
+        AppError::Validation(messages.join("; "))
+    })
+}
+
+/// Canonical form a handle is stored and looked up in: no leading `@`,
+/// lowercase.
+pub fn normalize_handle(value: &str) -> String {
+    value.trim().trim_start_matches('@').to_lowercase()
+}
+
+/// Format and reserved-word rules for a normalised handle: a letter, then
+/// letters, digits and single underscores, not ending in one.
+fn handle_error(handle: &str) -> Option<(&'static str, String)> {
+    let length = handle.chars().count();
+    if !(MIN_HANDLE_CHARS..=MAX_HANDLE_CHARS).contains(&length) {
+        return Some(("invalid_length", format!("Must be {} to {} characters", MIN_HANDLE_CHARS, MAX_HANDLE_CHARS)));
+    }
+    if !handle.starts_with(|c: char| c.is_ascii_lowercase())
+        || !handle.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        || handle.ends_with('_')
+        || handle.contains("__")
+    {
+        return Some(("invalid_format", "Must start with a letter and use only letters, digits and single underscores".to_string()));
+    }
+    if RESERVED_HANDLES.iter().any(|reserved| handle == *reserved || handle.starts_with("progress2win")) {
+        return Some(("reserved", "This handle is reserved".to_string()));
+    }
+    None
+}
+
+fn normalize(update: &UserUpdate) -> Result<ProfileChanges, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let mut error = |field: &'static str, code: &'static str, message: String| {
+        errors.push(FieldError { field, code, message });
+    };
+
+    // An empty string is treated like null: give up the handle
+    let handle = update.handle.as_ref().map(|value| {
+        let value = value.as_deref().map(normalize_handle).filter(|v| !v.is_empty())?;
+        if let Some((code, message)) = handle_error(&value) {
+            error("handle", code, message);
+        }
+        Some(value)
+    });
+
+    let mut name = |field: &'static str, value: &Option<String>| {
+        let value = value.as_deref()?.trim();
+        if value.is_empty() {
+            error(field, "required", "Must not be empty".to_string());
+        } else if value.chars().count() > MAX_NAME_CHARS {
+            error(field, "too_long", format!("Must be at most {} characters", MAX_NAME_CHARS));
+        } else if value.chars().any(char::is_control) {
+            error(field, "invalid_characters", "Must not contain control characters".to_string());
+        }
+        Some(value.to_string())
+    };
+    let first_name = name("first_name", &update.first_name);
+    let last_name = name("last_name", &update.last_name);
+
+    // An empty string is treated like null: clear the avatar
+    let avatar_url = update.avatar_url.as_ref().map(|value| {
+        let value = value.as_deref().map(str::trim).filter(|v| !v.is_empty())?;
+        if value.len() > MAX_AVATAR_URL_BYTES {
+            error("avatar_url", "too_long", format!("Must be at most {} bytes", MAX_AVATAR_URL_BYTES));
+        } else if !is_stored_avatar(value) && !Url::parse(value).is_ok_and(|url| url.scheme() == "https" && url.has_host()) {
+            error("avatar_url", "invalid_url", "Must be an https URL".to_string());
+        }
+        Some(value.to_string())
+    });
+
+    let goals = update.goals.as_ref().map(|goals| {
+        let goals: Vec<String> = goals.iter().map(|g| g.trim().to_string()).collect();
+        if goals.len() > MAX_GOALS {
+            error("goals", "too_many", format!("At most {} goals are allowed", MAX_GOALS));
+        }
+        if goals.iter().any(|g| g.is_empty()) {
+            error("goals", "empty_goal", "Goals must not be empty".to_string());
+        }
+        if goals.iter().any(|g| g.chars().count() > MAX_GOAL_CHARS) {
+            error("goals", "goal_too_long", format!("Each goal must be at most {} characters", MAX_GOAL_CHARS));
+        }
+        let mut seen = std::collections::HashSet::new();
+        if !goals.iter().all(|g| seen.insert(g.to_lowercase())) {
+            error("goals", "duplicate_goal", "Goals must be unique".to_string());
+        }
+        goals
+    });
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(ProfileChanges { handle, first_name, last_name, avatar_url, goals })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> UserUpdate {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn codes(json: &str) -> Vec<(&'static str, &'static str)> {
+        check(&parse(json)).into_iter().map(|e| (e.field, e.code)).collect()
+    }
+
+    #[test]
+    fn trims_names_and_goals() {
+        let changes = validate(&parse(r#"{"first_name": "  Jane ", "goals": [" Run a marathon "]}"#)).unwrap();
+
+        assert_eq!(changes.first_name.as_deref(), Some("Jane"));
+        assert_eq!(changes.last_name, None);
+        assert_eq!(changes.goals, Some(vec!["Run a marathon".to_string()]));
+        assert_eq!(changes.fields(), vec!["first_name", "goals"]);
+    }
+
+    #[test]
+    fn reports_every_invalid_field_at_once() {
+        let long_name = "x".repeat(MAX_NAME_CHARS + 1);
+
+        assert_eq!(
+            codes(&format!(r#"{{"first_name": " ", "last_name": "{}", "avatar_url": "http://example.com/a.png", "goals": ["Run", "run", ""]}}"#, long_name)),
+            vec![
+                ("first_name", "required"),
+                ("last_name", "too_long"),
+                ("avatar_url", "invalid_url"),
+                ("goals", "empty_goal"),
+                ("goals", "duplicate_goal"),
+            ],
+        );
+        assert_eq!(codes(r#"{"first_name": "Ja\u0000ne"}"#), vec![("first_name", "invalid_characters")]);
+        assert_eq!(codes(&format!(r#"{{"goals": {}}}"#, serde_json::json!(vec!["goal"; MAX_GOALS + 1]))), vec![("goals", "too_many"), ("goals", "duplicate_goal")]);
+    }
+
+    #[test]
+    fn null_or_empty_clears_the_avatar_and_omitted_keeps_it() {
+        assert!(matches!(validate(&parse(r#"{"avatar_url": null}"#)).unwrap().avatar_url, Some(None)));
+        assert!(matches!(validate(&parse(r#"{"avatar_url": " "}"#)).unwrap().avatar_url, Some(None)));
+        assert!(validate(&parse("{}")).unwrap().is_empty());
+
+        let changes = validate(&parse(r#"{"avatar_url": "https://example.com/a.png"}"#)).unwrap();
+        assert_eq!(changes.avatar_url, Some(Some("https://example.com/a.png".to_string())));
+    }
+
+    #[test]
+    fn handles_are_normalized_and_checked() {
+        let changes = validate(&parse(r#"{"handle": " @Jane_Doe "}"#)).unwrap();
+        assert_eq!(changes.handle, Some(Some("jane_doe".to_string())));
+
+        assert_eq!(codes(r#"{"handle": "jd"}"#), vec![("handle", "invalid_length")]);
+        for handle in ["1jane", "jane__doe", "jane_", "jane-doe", "jané"] {
+            assert_eq!(codes(&format!(r#"{{"handle": "{}"}}"#, handle)), vec![("handle", "invalid_format")], "{}", handle);
+        }
+        for handle in ["Admin", "progress2win_team"] {
+            assert_eq!(codes(&format!(r#"{{"handle": "{}"}}"#, handle)), vec![("handle", "reserved")], "{}", handle);
+        }
+        assert!(matches!(validate(&parse(r#"{"handle": ""}"#)).unwrap().handle, Some(None)));
+    }
+}
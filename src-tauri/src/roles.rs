@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::session::{authenticate, AuthUser};
+
+/// SQL expression for the role of a newly created account: the first
+/// account of an install (no users yet) becomes its admin.
+pub const NEW_ACCOUNT_ROLE_SQL: &str =
+    "(SELECT CASE WHEN EXISTS (SELECT 1 FROM users) THEN 'user' ELSE 'admin' END)";
+
+/// Stored in `users.role`. Every account has exactly one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+/// Actions beyond managing one's own account and data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewMetrics,
+    SendNotifications,
+    ViewAuditLog,
+    ManageSettings,
+    ManageRoles,
+    ManageSigningKeys,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "user" => Some(Role::User),
+            "moderator" => Some(Role::Moderator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Moderator => matches!(
+                permission,
+                Permission::ViewMetrics | Permission::SendNotifications | Permission::ViewAuditLog
+            ),
+            Role::User => false,
+        }
+    }
+}
+
+pub async fn role_of(db: &Database, user_id: i64) -> Result<Role, AppError> {
+    let row = sqlx::query("SELECT role FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(db.get_pool())
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    // An unknown value grants nothing rather than failing every command
+    Ok(Role::parse(&row.get::<String, _>("role")).unwrap_or(Role::User))
+}
+
+/// Like `authenticate`, and additionally requires the caller's current role
+/// to grant `permission`. The role is read on every call, so a revoked role
+/// takes effect without waiting for tokens to expire.
+pub async fn authorize(db: &Database, keys: &KeyStore, access_token: &str, permission: Permission) -> Result<AuthUser, AppError> {
+    let auth = authenticate(db, keys, access_token).await?;
+
+    if !role_of(db, auth.user_id).await?.allows(permission) {
+        return Err(AppError::Unauthorized("You do not have permission to do this".to_string()));
+    }
+
+    Ok(auth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::users::fetch_user;
+    use crate::models::SessionClient;
+    use crate::session::start_session;
+
+    async fn create_account(db: &Database, email: &str) -> i64 {
+        sqlx::query(&format!(
+            "INSERT INTO users (email, password_hash, first_name, last_name, role) VALUES (?, 'x', 'Jane', 'Doe', {})",
+            NEW_ACCOUNT_ROLE_SQL
+        ))
+        .bind(email)
+        .execute(db.get_pool())
+        .await
+        .unwrap()
+        .last_insert_rowid()
+    }
+
+    #[tokio::test]
+    async fn only_the_first_account_becomes_admin() {
+        let db = Database::in_memory().await.unwrap();
+        let first = create_account(&db, "first@example.com").await;
+        let second = create_account(&db, "second@example.com").await;
+
+        assert_eq!(role_of(&db, first).await.unwrap(), Role::Admin);
+        assert_eq!(role_of(&db, second).await.unwrap(), Role::User);
+    }
+
+    #[tokio::test]
+    async fn authorize_checks_the_current_role() {
+        let db = Database::in_memory().await.unwrap();
+        let keys = KeyStore::ephemeral();
+        create_account(&db, "admin@example.com").await;
+        let user_id = create_account(&db, "user@example.com").await;
+        let session = start_session(&db, &keys, user_id, &SessionClient::default()).await.unwrap();
+
+        let denied = authorize(&db, &keys, &session.access_token, Permission::ViewMetrics).await;
+        assert!(matches!(denied, Err(AppError::Unauthorized(_))));
+
+        // Granted roles apply to tokens issued before the change
+        sqlx::query("UPDATE users SET role = 'moderator' WHERE id = ?")
+            .bind(user_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        assert!(authorize(&db, &keys, &session.access_token, Permission::ViewMetrics).await.is_ok());
+        assert!(authorize(&db, &keys, &session.access_token, Permission::ManageSettings).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_stored_roles_grant_nothing() {
+        let db = Database::in_memory().await.unwrap();
+        create_account(&db, "admin@example.com").await;
+        let user_id = create_account(&db, "user@example.com").await;
+        sqlx::query("UPDATE users SET role = 'superuser' WHERE id = ?")
+            .bind(user_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        assert_eq!(role_of(&db, user_id).await.unwrap(), Role::User);
+        assert_eq!(fetch_user(&db, user_id).await.unwrap().role, Role::User);
+    }
+}
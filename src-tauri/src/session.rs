@@ -0,0 +1,461 @@
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use jsonwebtoken::{Algorithm, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use chrono::{DateTime, Utc, Duration};
+use uuid::Uuid;
+
+use crate::database::Database;
+use crate::error::AppError;
+use crate::keys::KeyStore;
+use crate::models::SessionClient;
+
+const ISSUER: &str = "progress2win";
+const AUDIENCE: &str = "progress2win-app";
+const ACCESS_TOKEN_TTL_DAYS: i64 = 7;
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+const SECOND_FACTOR_CHALLENGE_TTL_MINUTES: i64 = 5;
+const MAX_CLIENT_FIELD_CHARS: usize = 200;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    Access,
+    Refresh,
+    /// Proves the password step of a two-factor login succeeded
+    SecondFactor,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    #[serde(rename = "type")]
+    pub token_type: TokenType,
+    pub iss: String,
+    pub aud: String,
+    /// Unique token id; for refresh tokens it is stored on the `refresh_tokens` row
+    pub jti: String,
+    /// Session (`refresh_tokens.id`) an access token was issued for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i64>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl Claims {
+    fn new(token_type: TokenType, user_id: i64, sid: Option<i64>, expires_at: DateTime<Utc>) -> Self {
+        Claims {
+            sub: user_id.to_string(),
+            token_type,
+            iss: ISSUER.to_string(),
+            aud: AUDIENCE.to_string(),
+            jti: Uuid::new_v4().to_string(),
+            sid,
+            iat: Utc::now().timestamp(),
+            exp: expires_at.timestamp(),
+        }
+    }
+
+    pub fn user_id(&self) -> Result<i64, AppError> {
+        self.sub.parse()
+            .map_err(|_| AppError::Unauthorized("Invalid token subject".to_string()))
+    }
+}
+
+/// A freshly signed refresh token together with the values persisted
+/// alongside it in `refresh_tokens`.
+pub struct IssuedRefreshToken {
+    pub token: String,
+    pub jti: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Tokens handed back to the webview when a session starts or rotates.
+pub struct SessionTokens {
+    pub session_id: i64,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Identity of the caller, derived from a verified access token.
+/// Commands receive the raw token from the webview and resolve it with
+/// `authenticate` instead of trusting a `user_id` argument.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser {
+    pub user_id: i64,
+    pub session_id: i64,
+}
+
+/// Verifies an access token and checks that the session it was issued for
+/// (its refresh token family) is still alive and the account still active.
+pub async fn authenticate(db: &Database, keys: &KeyStore, access_token: &str) -> Result<AuthUser, AppError> {
+    if access_token.trim().is_empty() {
+        return Err(AppError::Unauthorized("Missing access token".to_string()));
+    }
+
+    let claims = verify_token(keys, access_token, TokenType::Access)?;
+    let user_id = claims.user_id()?;
+    let session_id = claims.sid
+        .ok_or_else(|| AppError::Unauthorized("Invalid access token".to_string()))?;
+
+    // A session stays alive while its family is unrevoked and its newest
+    // refresh token has not expired
+    let session_row = sqlx::query(
+        r#"
+        SELECT f.id FROM refresh_token_families f
+        JOIN users u ON u.id = f.user_id
+        WHERE f.id = ? AND f.user_id = ? AND f.revoked_at IS NULL AND u.is_active = 1
+          AND EXISTS (
+              SELECT 1 FROM refresh_tokens rt
+              WHERE rt.family_id = f.id AND rt.rotated_at IS NULL AND rt.expires_at > datetime('now')
+          )
+        "#
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_optional(db.get_pool())
+    .await?;
+
+    if session_row.is_none() {
+        return Err(AppError::Unauthorized("Session has been revoked".to_string()));
+    }
+
+    // Minute granularity is plenty for the session list and spares a write per command
+    sqlx::query("UPDATE refresh_token_families SET last_used_at = datetime('now') WHERE id = ? AND (last_used_at IS NULL OR last_used_at < datetime('now', '-1 minute'))")
+        .bind(session_id)
+        .execute(db.get_pool())
+        .await?;
+
+    Ok(AuthUser { user_id, session_id })
+}
+
+/// Starts a new session: a fresh refresh token family with its first token.
+pub async fn start_session(db: &Database, keys: &KeyStore, user_id: i64, client: &SessionClient) -> Result<SessionTokens, AppError> {
+    let refresh_token = generate_refresh_token(keys, user_id)?;
+
+    // Free text from the webview; keep it to something a list can display
+    let clean = |value: &Option<String>| {
+        value.as_deref()
+            .map(|v| v.trim().chars().take(MAX_CLIENT_FIELD_CHARS).collect::<String>())
+            .filter(|v| !v.is_empty())
+    };
+
+    let mut tx = db.get_pool().begin().await?;
+
+    let family = sqlx::query("INSERT INTO refresh_token_families (user_id, device_label, client_info, last_used_at) VALUES (?, ?, ?, datetime('now'))")
+        .bind(user_id)
+        .bind(clean(&client.device_label))
+        .bind(clean(&client.client_info))
+        .execute(&mut *tx)
+        .await?;
+    let session_id = family.last_insert_rowid();
+
+    sqlx::query("INSERT INTO refresh_tokens (user_id, token_hash, jti, family_id, expires_at) VALUES (?, ?, ?, ?, datetime(?))")
+        .bind(user_id)
+        .bind(keys.hash_token(&refresh_token.token))
+        .bind(&refresh_token.jti)
+        .bind(session_id)
+        .bind(refresh_token.expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(SessionTokens {
+        session_id,
+        access_token: generate_access_token(keys, user_id, session_id)?,
+        refresh_token: refresh_token.token,
+    })
+}
+
+/// Exchanges a refresh token for a new pair. The presented token is marked
+/// rotated and its successor recorded with it as parent. Presenting a token
+/// that was already rotated means it was copied, so the whole family is
+/// revoked and the account owner is notified.
+pub async fn rotate_session(db: &Database, keys: &KeyStore, refresh_token: &str) -> Result<(i64, SessionTokens), AppError> {
+    let claims = verify_token(keys, refresh_token, TokenType::Refresh)?;
+    let user_id = claims.user_id()?;
+
+    let token_row = sqlx::query(
+        r#"
+        SELECT rt.id, rt.family_id, rt.rotated_at IS NOT NULL AS rotated,
+               rt.expires_at > datetime('now') AS live, f.revoked_at IS NOT NULL AS revoked
+        FROM refresh_tokens rt
+        JOIN refresh_token_families f ON f.id = rt.family_id
+        WHERE rt.jti = ? AND rt.token_hash = ? AND rt.user_id = ?
+        "#
+    )
+    .bind(&claims.jti)
+    .bind(keys.hash_token(refresh_token))
+    .bind(user_id)
+    .fetch_optional(db.get_pool())
+    .await?
+    .ok_or_else(|| AppError::Unauthorized("Invalid refresh token".to_string()))?;
+
+    let token_id: i64 = token_row.get("id");
+    let session_id: i64 = token_row.get("family_id");
+
+    if token_row.get::<bool, _>("revoked") {
+        return Err(AppError::Unauthorized("Session has been revoked".to_string()));
+    }
+    if token_row.get::<bool, _>("rotated") {
+        return Err(handle_reuse(db, user_id, session_id).await);
+    }
+    if !token_row.get::<bool, _>("live") {
+        return Err(AppError::TokenExpired("Refresh token expired".to_string()));
+    }
+
+    let new_refresh_token = generate_refresh_token(keys, user_id)?;
+
+    let mut tx = db.get_pool().begin().await?;
+
+    // Guard against a concurrent rotation of the same token slipping through
+    let rotated = sqlx::query("UPDATE refresh_tokens SET rotated_at = datetime('now') WHERE id = ? AND rotated_at IS NULL")
+        .bind(token_id)
+        .execute(&mut *tx)
+        .await?;
+
+    if rotated.rows_affected() == 0 {
+        tx.rollback().await?;
+        return Err(handle_reuse(db, user_id, session_id).await);
+    }
+
+    sqlx::query("INSERT INTO refresh_tokens (user_id, token_hash, jti, family_id, parent_id, expires_at) VALUES (?, ?, ?, ?, ?, datetime(?))")
+        .bind(user_id)
+        .bind(keys.hash_token(&new_refresh_token.token))
+        .bind(&new_refresh_token.jti)
+        .bind(session_id)
+        .bind(token_id)
+        .bind(new_refresh_token.expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE refresh_token_families SET last_used_at = datetime('now') WHERE id = ?")
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok((user_id, SessionTokens {
+        session_id,
+        access_token: generate_access_token(keys, user_id, session_id)?,
+        refresh_token: new_refresh_token.token,
+    }))
+}
+
+/// Resolves the session a refresh token belongs to, whether or not it has
+/// already been rotated.
+pub async fn session_for_refresh_token(db: &Database, keys: &KeyStore, refresh_token: &str) -> Result<(i64, i64), AppError> {
+    let claims = verify_token(keys, refresh_token, TokenType::Refresh)?;
+    let user_id = claims.user_id()?;
+
+    let row = sqlx::query("SELECT family_id FROM refresh_tokens WHERE jti = ? AND token_hash = ? AND user_id = ?")
+        .bind(&claims.jti)
+        .bind(keys.hash_token(refresh_token))
+        .bind(user_id)
+        .fetch_optional(db.get_pool())
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Invalid refresh token".to_string()))?;
+
+    Ok((user_id, row.get("family_id")))
+}
+
+/// Revokes one session. Returns false if it was not an active session of this user.
+pub async fn revoke_session(db: &Database, user_id: i64, session_id: i64, reason: &str) -> Result<bool, AppError> {
+    let revoked = sqlx::query("UPDATE refresh_token_families SET revoked_at = datetime('now'), revoked_reason = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL")
+        .bind(reason)
+        .bind(session_id)
+        .bind(user_id)
+        .execute(db.get_pool())
+        .await?;
+
+    Ok(revoked.rows_affected() == 1)
+}
+
+/// Revokes every session of a user, optionally sparing the caller's own.
+/// Returns how many sessions were still active.
+pub async fn revoke_all_sessions(db: &Database, user_id: i64, except_session: Option<i64>, reason: &str) -> Result<u64, AppError> {
+    let revoked = sqlx::query("UPDATE refresh_token_families SET revoked_at = datetime('now'), revoked_reason = ? WHERE user_id = ? AND id IS NOT ? AND revoked_at IS NULL")
+        .bind(reason)
+        .bind(user_id)
+        .bind(except_session)
+        .execute(db.get_pool())
+        .await?;
+
+    Ok(revoked.rows_affected())
+}
+
+async fn handle_reuse(db: &Database, user_id: i64, session_id: i64) -> AppError {
+    if let Err(e) = revoke_session(db, user_id, session_id, "reuse_detected").await {
+        return e;
+    }
+
+    let notified = sqlx::query("INSERT INTO notifications (user_id, title, message, type) VALUES (?, ?, ?, 'warning')")
+        .bind(user_id)
+        .bind("Suspicious sign-in activity")
+        .bind("A previously used session token was presented again, so that session has been signed out. If this wasn't you, change your password.")
+        .execute(db.get_pool())
+        .await;
+
+    if let Err(e) = notified {
+        return e.into();
+    }
+
+    AppError::Unauthorized("Refresh token reuse detected; session revoked".to_string())
+}
+
+/// Verifies signature, expiry, issuer and audience, and rejects tokens of
+/// the wrong kind so an access token can never stand in for a refresh token.
+pub fn verify_token(keys: &KeyStore, token: &str, expected: TokenType) -> Result<Claims, AppError> {
+    let (invalid, expired) = match expected {
+        TokenType::Access => ("Invalid access token", "Access token expired"),
+        TokenType::Refresh => ("Invalid refresh token", "Refresh token expired"),
+        TokenType::SecondFactor => ("Invalid sign-in challenge", "Sign-in challenge expired; sign in again"),
+    };
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[ISSUER]);
+    validation.set_audience(&[AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+
+    let token_data = keys.verify::<Claims>(token, &validation)
+        .map_err(|e| match e.kind() {
+            ErrorKind::ExpiredSignature => AppError::TokenExpired(expired.to_string()),
+            _ => AppError::Unauthorized(invalid.to_string()),
+        })?;
+
+    if token_data.claims.token_type != expected {
+        return Err(AppError::Unauthorized(invalid.to_string()));
+    }
+
+    Ok(token_data.claims)
+}
+
+pub fn generate_second_factor_challenge(keys: &KeyStore, user_id: i64) -> Result<String, AppError> {
+    let expires_at = Utc::now() + Duration::minutes(SECOND_FACTOR_CHALLENGE_TTL_MINUTES);
+    keys.sign(&Claims::new(TokenType::SecondFactor, user_id, None, expires_at))
+}
+
+fn generate_access_token(keys: &KeyStore, user_id: i64, session_id: i64) -> Result<String, AppError> {
+    let expires_at = Utc::now() + Duration::days(ACCESS_TOKEN_TTL_DAYS);
+    keys.sign(&Claims::new(TokenType::Access, user_id, Some(session_id), expires_at))
+}
+
+fn generate_refresh_token(keys: &KeyStore, user_id: i64) -> Result<IssuedRefreshToken, AppError> {
+    let expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS);
+    let claims = Claims::new(TokenType::Refresh, user_id, None, expires_at);
+
+    Ok(IssuedRefreshToken {
+        token: keys.sign(&claims)?,
+        jti: claims.jti,
+        expires_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn setup() -> (Database, KeyStore, i64) {
+        let db = Database::in_memory().await.unwrap();
+        let user_id = sqlx::query("INSERT INTO users (email, password_hash, first_name, last_name) VALUES ('jane@example.com', 'x', 'Jane', 'Doe')")
+            .execute(db.get_pool())
+            .await
+            .unwrap()
+            .last_insert_rowid();
+
+        (db, KeyStore::ephemeral(), user_id)
+    }
+
+    async fn notification_count(db: &Database, user_id: i64) -> i64 {
+        sqlx::query("SELECT COUNT(*) AS count FROM notifications WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("count")
+    }
+
+    #[tokio::test]
+    async fn rotation_replaces_the_refresh_token_within_the_session() {
+        let (db, keys, user_id) = setup().await;
+        let first = start_session(&db, &keys, user_id, &SessionClient::default()).await.unwrap();
+
+        let (rotated_user, second) = rotate_session(&db, &keys, &first.refresh_token).await.unwrap();
+        assert_eq!(rotated_user, user_id);
+        assert_eq!(second.session_id, first.session_id);
+        assert_ne!(second.refresh_token, first.refresh_token);
+
+        let auth = authenticate(&db, &keys, &second.access_token).await.unwrap();
+        assert_eq!((auth.user_id, auth.session_id), (user_id, first.session_id));
+
+        // The successor rotates in turn
+        let (_, third) = rotate_session(&db, &keys, &second.refresh_token).await.unwrap();
+        assert_eq!(third.session_id, first.session_id);
+        assert_eq!(notification_count(&db, user_id).await, 0);
+    }
+
+    #[tokio::test]
+    async fn reusing_a_rotated_token_revokes_the_whole_family() {
+        let (db, keys, user_id) = setup().await;
+        let first = start_session(&db, &keys, user_id, &SessionClient::default()).await.unwrap();
+        let other = start_session(&db, &keys, user_id, &SessionClient::default()).await.unwrap();
+        let (_, second) = rotate_session(&db, &keys, &first.refresh_token).await.unwrap();
+
+        assert!(matches!(rotate_session(&db, &keys, &first.refresh_token).await, Err(AppError::Unauthorized(_))));
+
+        // Neither the thief's nor the owner's latest tokens work any more
+        assert!(rotate_session(&db, &keys, &second.refresh_token).await.is_err());
+        assert!(authenticate(&db, &keys, &second.access_token).await.is_err());
+        assert_eq!(notification_count(&db, user_id).await, 1);
+
+        let revoked_reason: String = sqlx::query("SELECT revoked_reason FROM refresh_token_families WHERE id = ?")
+            .bind(first.session_id)
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("revoked_reason");
+        assert_eq!(revoked_reason, "reuse_detected");
+
+        // Other sessions of the same account are left alone
+        authenticate(&db, &keys, &other.access_token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_refresh_tokens_do_not_rotate() {
+        let (db, keys, user_id) = setup().await;
+        let session = start_session(&db, &keys, user_id, &SessionClient::default()).await.unwrap();
+
+        // Stored in SQLite's own format, so comparisons against datetime('now') hold
+        let live: bool = sqlx::query("SELECT expires_at > datetime('now', '+29 days') AS live FROM refresh_tokens WHERE family_id = ?")
+            .bind(session.session_id)
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("live");
+        assert!(live);
+
+        sqlx::query("UPDATE refresh_tokens SET expires_at = datetime('now', '-1 minute') WHERE family_id = ?")
+            .bind(session.session_id)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        let error = rotate_session(&db, &keys, &session.refresh_token).await.err().unwrap();
+        assert_eq!(error.to_string(), AppError::TokenExpired("Refresh token expired".to_string()).to_string());
+        assert!(authenticate(&db, &keys, &session.access_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_access_tokens_are_reported_as_expired() {
+        let (db, keys, user_id) = setup().await;
+        let session = start_session(&db, &keys, user_id, &SessionClient::default()).await.unwrap();
+
+        let expired = keys.sign(&Claims::new(TokenType::Access, user_id, Some(session.session_id), Utc::now() - Duration::minutes(10))).unwrap();
+        assert!(matches!(authenticate(&db, &keys, &expired).await, Err(AppError::TokenExpired(_))));
+
+        // A refresh token is rejected outright rather than reported as expired
+        assert!(matches!(authenticate(&db, &keys, &session.refresh_token).await, Err(AppError::Unauthorized(_))));
+    }
+}
@@ -0,0 +1,68 @@
+//! Shared setup for tests that call commands directly, on a mock app with
+//! an in-memory database and throwaway keys.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use lettre::Message;
+use tauri::test::{mock_app, MockRuntime};
+use tauri::{App, Manager};
+
+use crate::avatars::AvatarStore;
+use crate::database::Database;
+use crate::keys::KeyStore;
+use crate::mail::{MailQueue, Mailer};
+use crate::models::SessionClient;
+use crate::session::{start_session, SessionTokens};
+
+pub const PASSWORD: &str = "Correct-horse-42";
+
+/// Accepts and discards every message.
+struct NullMailer;
+
+impl Mailer for NullMailer {
+    fn send(&self, _message: &Message) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub async fn app() -> App<MockRuntime> {
+    let app = mock_app();
+    let db = Database::in_memory().await.unwrap();
+
+    // Cheap hashing parameters keep the tests fast
+    for (key, value) in [("password_hash_memory_kib", "1024"), ("password_hash_iterations", "1")] {
+        sqlx::query("UPDATE settings SET value = ? WHERE key = ?")
+            .bind(value)
+            .bind(key)
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+    }
+
+    app.manage(db);
+    app.manage(KeyStore::ephemeral());
+    // Nothing is saved here; avatar tests use their own directory
+    app.manage(AvatarStore::in_dir(std::env::temp_dir().join("progress2win-test-avatars")));
+    app.manage(MailQueue::with_mailer(Arc::new(NullMailer), "Progress2Win <no-reply@progress2win.app>".parse().unwrap()));
+    app
+}
+
+/// Creates an active account whose password is `PASSWORD`.
+pub async fn create_user(app: &App<MockRuntime>, email: &str) -> i64 {
+    let password_hash = bcrypt::hash(PASSWORD, 4).unwrap();
+
+    sqlx::query("INSERT INTO users (email, password_hash, first_name, last_name) VALUES (?, ?, 'Jane', 'Doe')")
+        .bind(email)
+        .bind(password_hash)
+        .execute(app.state::<Database>().get_pool())
+        .await
+        .unwrap()
+        .last_insert_rowid()
+}
+
+pub async fn sign_in(app: &App<MockRuntime>, user_id: i64) -> SessionTokens {
+    start_session(&app.state::<Database>(), &app.state::<KeyStore>(), user_id, &SessionClient::default())
+        .await
+        .unwrap()
+}
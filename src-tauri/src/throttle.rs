@@ -0,0 +1,258 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::error::AppError;
+
+// Failures older than this no longer count towards backoff or lockout
+const FAILURE_WINDOW_HOURS: i64 = 24;
+const MAX_BACKOFF_SECONDS: i64 = 15 * 60;
+
+/// Persisted failed-login tracking. Attempts are keyed both by email
+/// (`email:<address>`) and by client identifier (`client:<id>`), so neither
+/// guessing one account's password nor spraying many accounts from one
+/// client goes unchecked. Sign-in code requests are counted the same way
+/// under their own `code_request:` keys.
+pub struct LoginThrottle {
+    backoff_threshold: i64,
+    lockout_threshold: i64,
+    lockout_duration: Duration,
+}
+
+impl LoginThrottle {
+    pub async fn load(db: &Database) -> Result<Self, sqlx::Error> {
+        let number = |value: Option<String>, default: i64| {
+            value.and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+
+        Ok(LoginThrottle {
+            backoff_threshold: number(db.get_setting("login_backoff_threshold").await?, 3),
+            lockout_threshold: number(db.get_setting("login_lockout_threshold").await?, 10),
+            lockout_duration: Duration::minutes(number(db.get_setting("login_lockout_minutes").await?, 15)),
+        })
+    }
+
+    pub fn email_key(email: &str) -> String {
+        format!("email:{}", email.trim().to_lowercase())
+    }
+
+    pub fn client_key(client_id: &str) -> String {
+        format!("client:{}", client_id)
+    }
+
+    pub fn code_request_key(key: &str) -> String {
+        format!("code_request:{}", key)
+    }
+
+    /// Fails while a key is locked out or still inside its backoff delay.
+    /// Must run before any password verification.
+    pub async fn check(&self, db: &Database, key: &str) -> Result<(), AppError> {
+        let row = sqlx::query("SELECT failure_count, last_failure_at, locked_until FROM login_attempts WHERE key = ?")
+            .bind(key)
+            .fetch_optional(db.get_pool())
+            .await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(()),
+        };
+
+        let now = Utc::now();
+        let failure_count: i64 = row.get("failure_count");
+        let last_failure_at: DateTime<Utc> = row.get("last_failure_at");
+        let locked_until: Option<DateTime<Utc>> = row.get("locked_until");
+
+        if let Some(locked_until) = locked_until.filter(|until| *until > now) {
+            let minutes = (locked_until - now).num_minutes() + 1;
+            return Err(AppError::Unauthorized(format!(
+                "Too many failed login attempts; sign-in is locked for {} more minute(s)", minutes
+            )));
+        }
+
+        if failure_count >= self.backoff_threshold && last_failure_at > now - Duration::hours(FAILURE_WINDOW_HOURS) {
+            let retry_at = last_failure_at + backoff(failure_count - self.backoff_threshold);
+            if retry_at > now {
+                let seconds = (retry_at - now).num_seconds() + 1;
+                return Err(AppError::Unauthorized(format!(
+                    "Too many failed login attempts; try again in {} second(s)", seconds
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts a failed attempt. Returns true when this failure started a lockout.
+    pub async fn record_failure(&self, db: &Database, key: &str) -> Result<bool, AppError> {
+        let now = Utc::now();
+
+        // One statement, so concurrent failures cannot overwrite each other's
+        // count. An expired lockout or stale failures start the count over.
+        let row = sqlx::query(
+            r#"
+            INSERT INTO login_attempts (key, failure_count, last_failure_at, locked_until)
+            VALUES (?1, 1, ?2, CASE WHEN 1 >= ?3 THEN ?4 END)
+            ON CONFLICT(key) DO UPDATE SET
+                failure_count = CASE WHEN locked_until <= ?2 OR last_failure_at <= ?5 THEN 1 ELSE failure_count + 1 END,
+                last_failure_at = ?2,
+                locked_until = CASE
+                    WHEN (CASE WHEN locked_until <= ?2 OR last_failure_at <= ?5 THEN 1 ELSE failure_count + 1 END) >= ?3 THEN ?4
+                END
+            RETURNING locked_until
+            "#
+        )
+        .bind(key)
+        .bind(now)
+        .bind(self.lockout_threshold)
+        .bind(now + self.lockout_duration)
+        .bind(now - Duration::hours(FAILURE_WINDOW_HOURS))
+        .fetch_one(db.get_pool())
+        .await?;
+
+        Ok(row.get::<Option<DateTime<Utc>>, _>("locked_until").is_some())
+    }
+
+    /// Drops counters that no longer affect anything: failures outside the
+    /// window with no lockout still running.
+    pub async fn prune_stale(db: &Database) -> Result<u64, sqlx::Error> {
+        let cutoff = Utc::now() - Duration::hours(FAILURE_WINDOW_HOURS);
+
+        let pruned = sqlx::query("DELETE FROM login_attempts WHERE last_failure_at <= ? AND (locked_until IS NULL OR locked_until <= ?)")
+            .bind(cutoff)
+            .bind(Utc::now())
+            .execute(db.get_pool())
+            .await?;
+
+        Ok(pruned.rows_affected())
+    }
+
+    /// Clears the counter after a successful sign-in.
+    pub async fn record_success(&self, db: &Database, key: &str) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM login_attempts WHERE key = ?")
+            .bind(key)
+            .execute(db.get_pool())
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// 1s, 2s, 4s, ... capped at `MAX_BACKOFF_SECONDS`.
+fn backoff(excess_failures: i64) -> Duration {
+    let seconds = 2i64.saturating_pow(excess_failures.clamp(0, 32) as u32);
+    Duration::seconds(seconds.min(MAX_BACKOFF_SECONDS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            backoff_threshold: 3,
+            lockout_threshold: 5,
+            lockout_duration: Duration::minutes(15),
+        }
+    }
+
+    async fn failure_count(db: &Database, key: &str) -> Option<i64> {
+        sqlx::query("SELECT failure_count FROM login_attempts WHERE key = ?")
+            .bind(key)
+            .fetch_optional(db.get_pool())
+            .await
+            .unwrap()
+            .map(|row| row.get("failure_count"))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff(0), Duration::seconds(1));
+        assert_eq!(backoff(1), Duration::seconds(2));
+        assert_eq!(backoff(4), Duration::seconds(16));
+        assert_eq!(backoff(40), Duration::seconds(MAX_BACKOFF_SECONDS));
+        assert_eq!(backoff(-1), Duration::seconds(1));
+    }
+
+    #[tokio::test]
+    async fn backs_off_once_failures_reach_the_threshold() {
+        let db = Database::in_memory().await.unwrap();
+        let throttle = throttle();
+        let key = LoginThrottle::email_key(" Jane@Example.com ");
+        assert_eq!(key, "email:jane@example.com");
+
+        for _ in 0..2 {
+            assert!(!throttle.record_failure(&db, &key).await.unwrap());
+            throttle.check(&db, &key).await.unwrap();
+        }
+
+        throttle.record_failure(&db, &key).await.unwrap();
+        let error = throttle.check(&db, &key).await.unwrap_err();
+        assert!(error.to_string().contains("try again in"), "{}", error);
+
+        // Other keys are unaffected
+        throttle.check(&db, &LoginThrottle::client_key("device-1")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn locks_out_at_the_lockout_threshold() {
+        let db = Database::in_memory().await.unwrap();
+        let throttle = throttle();
+
+        let mut locks = Vec::new();
+        for _ in 0..5 {
+            locks.push(throttle.record_failure(&db, "client:device-1").await.unwrap());
+        }
+        assert_eq!(locks, [false, false, false, false, true]);
+
+        let error = throttle.check(&db, "client:device-1").await.unwrap_err();
+        assert!(error.to_string().contains("locked"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn concurrent_failures_are_all_counted() {
+        let db = Database::in_memory().await.unwrap();
+        let throttle = throttle();
+
+        let attempts = (0..4).map(|_| throttle.record_failure(&db, "email:jane@example.com"));
+        futures_util::future::try_join_all(attempts).await.unwrap();
+
+        assert_eq!(failure_count(&db, "email:jane@example.com").await, Some(4));
+    }
+
+    #[tokio::test]
+    async fn success_clears_the_count() {
+        let db = Database::in_memory().await.unwrap();
+        let throttle = throttle();
+
+        for _ in 0..3 {
+            throttle.record_failure(&db, "email:jane@example.com").await.unwrap();
+        }
+        throttle.record_success(&db, "email:jane@example.com").await.unwrap();
+
+        assert_eq!(failure_count(&db, "email:jane@example.com").await, None);
+        throttle.check(&db, "email:jane@example.com").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_lockouts_and_stale_failures_start_over() {
+        let db = Database::in_memory().await.unwrap();
+        let throttle = throttle();
+        let long_ago = Utc::now() - Duration::hours(FAILURE_WINDOW_HOURS + 1);
+
+        for (key, locked_until) in [("email:locked@example.com", Some(Utc::now() - Duration::minutes(1))), ("email:stale@example.com", None)] {
+            sqlx::query("INSERT INTO login_attempts (key, failure_count, last_failure_at, locked_until) VALUES (?, 9, ?, ?)")
+                .bind(key)
+                .bind(long_ago)
+                .bind(locked_until)
+                .execute(db.get_pool())
+                .await
+                .unwrap();
+
+            throttle.check(&db, key).await.unwrap();
+            throttle.record_failure(&db, key).await.unwrap();
+            assert_eq!(failure_count(&db, key).await, Some(1));
+        }
+
+        assert_eq!(LoginThrottle::prune_stale(&db).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+
+// RFC 6238 defaults, which every authenticator app supports
+const PERIOD_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+// Accept one step of clock drift either way
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const ISSUER: &str = "Progress2Win";
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// New random 160-bit shared secret, base32-encoded for authenticator apps.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// `otpauth://` URI the frontend renders as a QR code.
+pub fn otpauth_uri(secret: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(ISSUER),
+        urlencoding::encode(account),
+        secret,
+        urlencoding::encode(ISSUER),
+        DIGITS,
+        PERIOD_SECONDS
+    )
+}
+
+/// Checks a code against the current time step and its neighbours. Returns
+/// the matched step so callers can reject replays of an already used code
+/// (anything at or before `last_used_step`).
+pub fn verify_code(secret: &str, code: &str, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current_step = Utc::now().timestamp() / PERIOD_SECONDS;
+
+    (current_step - ALLOWED_DRIFT_STEPS..=current_step + ALLOWED_DRIFT_STEPS)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| format!("{:0width$}", hotp(&key, *step as u64), width = DIGITS as usize) == code)
+}
+
+/// One-time recovery codes shown to the user once, e.g. `k7pq-x3mz`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    let mut random_part = |len: usize| -> String {
+        (0..len)
+            .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+            .collect()
+    };
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| format!("{}-{}", random_part(4), random_part(4)))
+        .collect()
+}
+
+/// Canonical form a recovery code is hashed in, so input is forgiving about
+/// case, spacing and the separating '-'.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase().replace([' ', '-'], "")
+}
+
+// RFC 4226 HOTP with dynamic truncation
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA-1 seed; codes are the last six of its eight digits
+    const RFC_SEED: &[u8] = b"12345678901234567890";
+    const RFC_VECTORS: &[(i64, u32)] = &[
+        (59, 287_082),
+        (1_111_111_109, 81_804),
+        (1_111_111_111, 50_471),
+        (1_234_567_890, 5_924),
+        (2_000_000_000, 279_037),
+        (20_000_000_000, 353_130),
+    ];
+
+    fn code_at(secret: &str, step: i64) -> String {
+        let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+        format!("{:06}", hotp(&key, step as u64))
+    }
+
+    #[test]
+    fn matches_rfc_6238_test_vectors() {
+        for (time, expected) in RFC_VECTORS {
+            assert_eq!(hotp(RFC_SEED, (time / PERIOD_SECONDS) as u64), *expected, "at {}", time);
+        }
+    }
+
+    #[test]
+    fn accepts_the_current_code_and_returns_its_step() {
+        let secret = BASE32_NOPAD.encode(RFC_SEED);
+        let step = Utc::now().timestamp() / PERIOD_SECONDS;
+
+        let matched = verify_code(&secret, &code_at(&secret, step), None).unwrap();
+        assert_eq!(matched, step);
+        assert_eq!(verify_code(&secret, &format!(" {} ", code_at(&secret, step)), None), Some(matched));
+    }
+
+    #[test]
+    fn rejects_a_replayed_step() {
+        let secret = generate_secret();
+        let step = Utc::now().timestamp() / PERIOD_SECONDS;
+        let code = code_at(&secret, step);
+
+        let used = verify_code(&secret, &code, None).unwrap();
+        assert_eq!(verify_code(&secret, &code, Some(used)), None);
+        // An earlier step stays rejected once a later one was used
+        assert_eq!(verify_code(&secret, &code_at(&secret, step - 1), Some(used)), None);
+    }
+
+    #[test]
+    fn rejects_codes_outside_the_drift_window() {
+        let secret = generate_secret();
+        let step = Utc::now().timestamp() / PERIOD_SECONDS;
+
+        assert_eq!(verify_code(&secret, &code_at(&secret, step - 5), None), None);
+        assert_eq!(verify_code(&secret, &code_at(&secret, step + 5), None), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let secret = generate_secret();
+        for code in ["", "12345", "1234567", "12a456"] {
+            assert_eq!(verify_code(&secret, code, None), None, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn normalizes_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        let code = &codes[0];
+        assert_eq!(normalize_recovery_code(code), code.replace('-', ""));
+        assert_eq!(normalize_recovery_code(&format!("  {} ", code.to_uppercase().replace('-', " "))), code.replace('-', ""));
+    }
+}
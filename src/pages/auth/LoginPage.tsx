@@ -3,9 +3,10 @@ import { Link, useNavigate } from 'react-router-dom';
 import { useForm } from 'react-hook-form';
 import { zodResolver } from '@hookform/resolvers/zod';
 import { z } from 'zod';
-import { Mail, Lock, Eye, EyeOff } from 'lucide-react';
-import { authApi } from '../../services/api';
+import { Mail, Lock, Eye, EyeOff, ShieldCheck } from 'lucide-react';
+import { authApi, isTokenExpired } from '../../services/api';
 import { useAuthStore } from '../../stores/authStore';
+import { AuthResponse } from '../../types';
 
 const loginSchema = z.object({
   email: z.string().email('Adresse email invalide'),
@@ -18,6 +19,8 @@ export const LoginPage: React.FC = () => {
   const [showPassword, setShowPassword] = useState(false);
   const [isLoading, setIsLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
+  const [challengeToken, setChallengeToken] = useState<string | null>(null);
+  const [code, setCode] = useState('');
 
   const navigate = useNavigate();
   const { login } = useAuthStore();
@@ -39,16 +42,13 @@ export const LoginPage: React.FC = () => {
     setError(null);
 
     try {
-      const authResponse = await authApi.login(data);
-      console.log('Login successful:', authResponse);
-      login(authResponse);
-
-      // If password reset is required, redirect to change password page
-      if (authResponse.passwordResetRequired) {
-        navigate('/change-password');
-      } else {
-        navigate('/');
+      const loginResponse = await authApi.login(data);
+      if (loginResponse.status === 'second_factor_required') {
+        setChallengeToken(loginResponse.challengeToken);
+        return;
       }
+      console.log('Login successful:', loginResponse);
+      completeLogin(loginResponse);
     } catch (err: any) {
       console.error('Login error:', err);
       setError(err.message || 'Login failed');
@@ -57,6 +57,39 @@ export const LoginPage: React.FC = () => {
     }
   };
 
+  const onSubmitCode = async (e: React.FormEvent) => {
+    e.preventDefault();
+    if (!challengeToken) return;
+
+    setIsLoading(true);
+    setError(null);
+
+    try {
+      completeLogin(await authApi.verifySecondFactor(challengeToken, code));
+    } catch (err: any) {
+      console.error('Second factor error:', err);
+      setError(err.message || err || 'Verification failed');
+      // An expired challenge means starting over from the password
+      if (isTokenExpired(err)) {
+        setChallengeToken(null);
+        setCode('');
+      }
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
+  const completeLogin = (authResponse: AuthResponse) => {
+    login(authResponse);
+
+    // If password reset is required, redirect to change password page
+    if (authResponse.passwordResetRequired) {
+      navigate('/change-password');
+    } else {
+      navigate('/');
+    }
+  };
+
   return (
     <div className="min-h-screen bg-[#FFF5E1] flex items-center justify-center p-4">
       <div className="w-full max-w-md">
@@ -70,86 +103,132 @@ export const LoginPage: React.FC = () => {
         </div>
 
         <div className="bg-white border-3 border-black rounded-2xl p-6 shadow-[5px_5px_0_0_rgba(0,0,0,1)]">
-          <form onSubmit={handleSubmit(onSubmit)} noValidate>
-            {error && (
-              <div className="mb-6 bg-white border-2 border-black rounded-xl p-4 shadow-[2px_2px_0_0_rgba(0,0,0,1)]">
-                <p className="text-sm text-black">{error}</p>
-              </div>
-            )}
-
-            <div className="space-y-5">
-              <div>
-                <label className="block text-sm text-black mb-2">Adresse email</label>
-                <div className="relative">
-                  <div className="absolute left-3 top-1/2 -translate-y-1/2 text-black/50">
-                    <Mail className="w-5 h-5" />
+          {challengeToken ? (
+            <form onSubmit={onSubmitCode} noValidate>
+              {error && (
+                <div className="mb-6 bg-white border-2 border-black rounded-xl p-4 shadow-[2px_2px_0_0_rgba(0,0,0,1)]">
+                  <p className="text-sm text-black">{error}</p>
+                </div>
+              )}
+
+              <div className="space-y-5">
+                <div>
+                  <label className="block text-sm text-black mb-2">Code de vérification</label>
+                  <div className="relative">
+                    <div className="absolute left-3 top-1/2 -translate-y-1/2 text-black/50">
+                      <ShieldCheck className="w-5 h-5" />
+                    </div>
+                    <input
+                      type="text"
+                      inputMode="text"
+                      autoComplete="one-time-code"
+                      autoFocus
+                      placeholder="Code de ton application ou code de secours"
+                      value={code}
+                      onChange={(e) => setCode(e.target.value)}
+                      className="w-full pl-11 pr-4 py-2.5 border-2 border-black rounded-lg shadow-[2px_2px_0_0_rgba(0,0,0,1)] focus:shadow-[3px_3px_0_0_rgba(0,0,0,1)] focus:outline-none transition-all bg-white"
+                    />
                   </div>
-                  <input
-                    type="email"
-                    placeholder="Entre ton email"
-                    className={`w-full pl-11 pr-4 py-2.5 border-2 border-black rounded-lg shadow-[2px_2px_0_0_rgba(0,0,0,1)] focus:shadow-[3px_3px_0_0_rgba(0,0,0,1)] focus:outline-none transition-all bg-white ${
-                      errors.email ? 'border-red-500' : ''
-                    }`}
-                    {...register('email')}
-                  />
                 </div>
-                {errors.email && (
-                  <p className="text-xs text-red-600 mt-1">{errors.email.message}</p>
-                )}
+
+                <button
+                  type="submit"
+                  disabled={isLoading || !code.trim()}
+                  className="w-full bg-[#9D4EDD] border-2 border-black rounded-xl font-semibold text-white py-3 px-5 shadow-[4px_4px_0_0_rgba(0,0,0,1)] hover:shadow-[3px_3px_0_0_rgba(0,0,0,1)] hover:translate-x-[1px] hover:translate-y-[1px] transition-all duration-150 flex items-center justify-center gap-2 disabled:opacity-50 disabled:cursor-not-allowed"
+                >
+                  {isLoading ? (
+                    <>
+                      <div className="w-5 h-5 border-2 border-white border-t-transparent rounded-full animate-spin" />
+                      Vérification...
+                    </>
+                  ) : (
+                    'Vérifier'
+                  )}
+                </button>
               </div>
+            </form>
+          ) : (
+            <form onSubmit={handleSubmit(onSubmit)} noValidate>
+              {error && (
+                <div className="mb-6 bg-white border-2 border-black rounded-xl p-4 shadow-[2px_2px_0_0_rgba(0,0,0,1)]">
+                  <p className="text-sm text-black">{error}</p>
+                </div>
+              )}
+
+              <div className="space-y-5">
+                <div>
+                  <label className="block text-sm text-black mb-2">Adresse email</label>
+                  <div className="relative">
+                    <div className="absolute left-3 top-1/2 -translate-y-1/2 text-black/50">
+                      <Mail className="w-5 h-5" />
+                    </div>
+                    <input
+                      type="email"
+                      placeholder="Entre ton email"
+                      className={`w-full pl-11 pr-4 py-2.5 border-2 border-black rounded-lg shadow-[2px_2px_0_0_rgba(0,0,0,1)] focus:shadow-[3px_3px_0_0_rgba(0,0,0,1)] focus:outline-none transition-all bg-white ${
+                        errors.email ? 'border-red-500' : ''
+                      }`}
+                      {...register('email')}
+                    />
+                  </div>
+                  {errors.email && (
+                    <p className="text-xs text-red-600 mt-1">{errors.email.message}</p>
+                  )}
+                </div>
 
-              <div>
-                <label className="block text-sm text-black mb-2">Mot de passe</label>
-                <div className="relative">
-                  <div className="absolute left-3 top-1/2 -translate-y-1/2 text-black/50">
-                    <Lock className="w-5 h-5" />
+                <div>
+                  <label className="block text-sm text-black mb-2">Mot de passe</label>
+                  <div className="relative">
+                    <div className="absolute left-3 top-1/2 -translate-y-1/2 text-black/50">
+                      <Lock className="w-5 h-5" />
+                    </div>
+                    <input
+                      type={showPassword ? 'text' : 'password'}
+                      placeholder="Entre ton mot de passe"
+                      className={`w-full pl-11 pr-12 py-2.5 border-2 border-black rounded-lg shadow-[2px_2px_0_0_rgba(0,0,0,1)] focus:shadow-[3px_3px_0_0_rgba(0,0,0,1)] focus:outline-none transition-all bg-white ${
+                        errors.password ? 'border-red-500' : ''
+                      }`}
+                      {...register('password')}
+                    />
+                    <button
+                      type="button"
+                      onClick={() => setShowPassword(!showPassword)}
+                      className="absolute right-3 top-1/2 -translate-y-1/2 text-black/50 hover:text-black transition-colors"
+                    >
+                      {showPassword ? <EyeOff className="w-5 h-5" /> : <Eye className="w-5 h-5" />}
+                    </button>
                   </div>
-                  <input
-                    type={showPassword ? 'text' : 'password'}
-                    placeholder="Entre ton mot de passe"
-                    className={`w-full pl-11 pr-12 py-2.5 border-2 border-black rounded-lg shadow-[2px_2px_0_0_rgba(0,0,0,1)] focus:shadow-[3px_3px_0_0_rgba(0,0,0,1)] focus:outline-none transition-all bg-white ${
-                      errors.password ? 'border-red-500' : ''
-                    }`}
-                    {...register('password')}
-                  />
-                  <button
-                    type="button"
-                    onClick={() => setShowPassword(!showPassword)}
-                    className="absolute right-3 top-1/2 -translate-y-1/2 text-black/50 hover:text-black transition-colors"
+                  {errors.password && (
+                    <p className="text-xs text-red-600 mt-1">{errors.password.message}</p>
+                  )}
+                </div>
+
+                <div className="flex items-center justify-between">
+                  <Link
+                    to="/forgot-password"
+                    className="text-sm font-semibold text-[#9D4EDD] hover:text-[#7B2CBF] transition-colors"
                   >
-                    {showPassword ? <EyeOff className="w-5 h-5" /> : <Eye className="w-5 h-5" />}
-                  </button>
+                    Mot de passe oublié?
+                  </Link>
                 </div>
-                {errors.password && (
-                  <p className="text-xs text-red-600 mt-1">{errors.password.message}</p>
-                )}
-              </div>
 
-              <div className="flex items-center justify-between">
-                <Link
-                  to="/forgot-password"
-                  className="text-sm font-semibold text-[#9D4EDD] hover:text-[#7B2CBF] transition-colors"
+                <button
+                  type="submit"
+                  disabled={isLoading}
+                  className="w-full bg-[#9D4EDD] border-2 border-black rounded-xl font-semibold text-white py-3 px-5 shadow-[4px_4px_0_0_rgba(0,0,0,1)] hover:shadow-[3px_3px_0_0_rgba(0,0,0,1)] hover:translate-x-[1px] hover:translate-y-[1px] transition-all duration-150 flex items-center justify-center gap-2 disabled:opacity-50 disabled:cursor-not-allowed"
                 >
-                  Mot de passe oublié?
-                </Link>
+                  {isLoading ? (
+                    <>
+                      <div className="w-5 h-5 border-2 border-white border-t-transparent rounded-full animate-spin" />
+                      Connexion...
+                    </>
+                  ) : (
+                    'Connexion'
+                  )}
+                </button>
               </div>
-
-              <button
-                type="submit"
-                disabled={isLoading}
-                className="w-full bg-[#9D4EDD] border-2 border-black rounded-xl font-semibold text-white py-3 px-5 shadow-[4px_4px_0_0_rgba(0,0,0,1)] hover:shadow-[3px_3px_0_0_rgba(0,0,0,1)] hover:translate-x-[1px] hover:translate-y-[1px] transition-all duration-150 flex items-center justify-center gap-2 disabled:opacity-50 disabled:cursor-not-allowed"
-              >
-                {isLoading ? (
-                  <>
-                    <div className="w-5 h-5 border-2 border-white border-t-transparent rounded-full animate-spin" />
-                    Connexion...
-                  </>
-                ) : (
-                  'Connexion'
-                )}
-              </button>
-            </div>
-          </form>
+            </form>
+          )}
 
           <div className="mt-6 text-center">
             <p className="text-sm text-black/70">
@@ -46,13 +46,18 @@ export const RegisterPage: React.FC = () => {
       const user = await authApi.register(userData);
 
       // Auto-login after registration
-      const authResponse = await authApi.login({
+      const loginResponse = await authApi.login({
         email: data.email,
         password: data.password,
       });
 
-      login(authResponse);
-      navigate('/');
+      // A brand new account has no second factor yet
+      if (loginResponse.status === 'authenticated') {
+        login(loginResponse);
+        navigate('/');
+      } else {
+        navigate('/login');
+      }
     } catch (err: any) {
       setError(err.message || 'Registration failed');
     } finally {
@@ -1,11 +1,13 @@
 import { invoke } from '@tauri-apps/api/core';
 import { config, isTauriBackend } from '../config';
+import { useAuthStore } from '../stores/authStore';
 import {
   User,
   UserCreate,
   UserUpdate,
   LoginRequest,
   AuthResponse,
+  LoginResponse,
   Progress,
   ProgressCreate,
   ProgressUpdate,
@@ -198,6 +200,86 @@ const expressClient = new ExpressApiClient(config.expressApiUrl || '');
 // Export the API client for direct use
 export const api = expressClient;
 
+// Tauri returns auth payloads with snake_case keys
+interface TauriAuthResponse {
+  access_token: string;
+  refresh_token: string;
+  user: User;
+}
+
+const fromTauriAuth = (response: TauriAuthResponse): AuthResponse => ({
+  accessToken: response.access_token,
+  refreshToken: response.refresh_token,
+  user: response.user,
+});
+
+type TauriLoginResponse =
+  | ({ status: 'authenticated' } & TauriAuthResponse)
+  | { status: 'second_factor_required'; challenge_token: string };
+
+// Stable per-install id the backend throttles sign-in attempts by
+const getClientId = (): string => {
+  let clientId = localStorage.getItem('client-id');
+  if (!clientId) {
+    clientId = crypto.randomUUID();
+    localStorage.setItem('client-id', clientId);
+  }
+  return clientId;
+};
+
+// Device details shown in the session list
+const sessionClient = () => ({ client_info: navigator.userAgent });
+
+// Refresh tokens are single use: concurrent commands share one refresh,
+// since spending the same token twice revokes the whole session
+let tauriRefresh: Promise<string | null> | null = null;
+
+const refreshTauriSession = (): Promise<string | null> => {
+  if (!tauriRefresh) {
+    tauriRefresh = (async () => {
+      const { refreshToken } = useAuthStore.getState();
+      if (!refreshToken) {
+        return null;
+      }
+
+      try {
+        const response = fromTauriAuth(await invoke<TauriAuthResponse>('refresh_token', { refreshToken }));
+        useAuthStore.setState({ accessToken: response.accessToken, refreshToken: response.refreshToken });
+        return response.accessToken;
+      } catch (error) {
+        console.error('Token refresh failed:', error);
+        useAuthStore.getState().logout();
+        return null;
+      }
+    })().finally(() => {
+      tauriRefresh = null;
+    });
+  }
+  return tauriRefresh;
+};
+
+// Tauri commands fail with "<kind>: <message>" strings; this kind marks a
+// token that was valid but has run out, as opposed to a rejected one
+export const isTokenExpired = (error: unknown): boolean => String(error).startsWith('Token expired:');
+
+// Invokes a command that authenticates with the stored access token,
+// refreshing it once if it has expired
+const invokeAuthed = async <T>(command: string, args: Record<string, unknown> = {}): Promise<T> => {
+  try {
+    return await invoke<T>(command, { ...args, accessToken: useAuthStore.getState().accessToken });
+  } catch (error) {
+    if (!isTokenExpired(error)) {
+      throw error;
+    }
+
+    const accessToken = await refreshTauriSession();
+    if (!accessToken) {
+      throw error;
+    }
+    return await invoke<T>(command, { ...args, accessToken });
+  }
+};
+
 // Auth API
 export const authApi = {
   register: async (userData: UserCreate): Promise<User> => {
@@ -208,14 +290,33 @@ export const authApi = {
     }
   },
 
-  login: async (loginData: LoginRequest): Promise<AuthResponse> => {
+  login: async (loginData: LoginRequest): Promise<LoginResponse> => {
     if (isTauriBackend()) {
-      return await invoke('login_user', { loginData });
+      const response = await invoke<TauriLoginResponse>('login_user', {
+        loginData,
+        clientId: getClientId(),
+        client: sessionClient(),
+      });
+      if (response.status === 'second_factor_required') {
+        return { status: response.status, challengeToken: response.challenge_token };
+      }
+      return { status: response.status, ...fromTauriAuth(response) };
     } else {
       const response = await expressClient.post<AuthResponse>('/auth/login', loginData);
       expressClient.setToken(response.accessToken);
-      return response;
+      return { status: 'authenticated', ...response };
+    }
+  },
+
+  verifySecondFactor: async (challengeToken: string, code: string): Promise<AuthResponse> => {
+    if (isTauriBackend()) {
+      return fromTauriAuth(await invoke<TauriAuthResponse>('verify_second_factor', {
+        challengeToken,
+        code,
+        client: sessionClient(),
+      }));
     }
+    throw new Error('Two-factor authentication not supported in Express backend');
   },
 
   logout: async (refreshToken: string): Promise<void> => {
@@ -229,7 +330,7 @@ export const authApi = {
 
   refreshToken: async (refreshToken: string): Promise<AuthResponse> => {
     if (isTauriBackend()) {
-      return await invoke('refresh_token', { refreshToken });
+      return fromTauriAuth(await invoke<TauriAuthResponse>('refresh_token', { refreshToken }));
     } else {
       const response = await expressClient.post<AuthResponse>('/auth/refresh', { refreshToken });
       expressClient.setToken(response.accessToken);
@@ -253,9 +354,9 @@ export const authApi = {
     }
   },
 
-  getCurrentUser: async (userId: number): Promise<User> => {
+  getCurrentUser: async (): Promise<User> => {
     if (isTauriBackend()) {
-      return await invoke('get_current_user', { userId });
+      return await invokeAuthed('get_current_user');
     } else {
       return await expressClient.get('/auth/me');
     }
@@ -263,7 +364,7 @@ export const authApi = {
 
   changePassword: async (currentPassword: string, newPassword: string): Promise<void> => {
     if (isTauriBackend()) {
-      return await invoke('change_password', { currentPassword, newPassword });
+      return await invokeAuthed('change_password', { currentPassword, newPassword });
     } else {
       await expressClient.post('/auth/change-password', { currentPassword, newPassword });
     }
@@ -274,7 +375,7 @@ export const authApi = {
 export const userApi = {
   getProfile: async (userId: number): Promise<User> => {
     if (isTauriBackend()) {
-      return await invoke('get_user_profile', { userId });
+      return await invokeAuthed('get_user_profile');
     } else {
       return await expressClient.get(`/users/${userId}`);
     }
@@ -282,15 +383,15 @@ export const userApi = {
 
   updateProfile: async (userId: number, updateData: UserUpdate): Promise<User> => {
     if (isTauriBackend()) {
-      return await invoke('update_user_profile', { userId, updateData });
+      return await invokeAuthed('update_user_profile', { updateData });
     } else {
       return await expressClient.put(`/users/${userId}`, updateData);
     }
   },
 
-  deleteAccount: async (userId: number): Promise<void> => {
+  deleteAccount: async (userId: number, permanent?: boolean): Promise<void> => {
     if (isTauriBackend()) {
-      return await invoke('delete_user_account', { userId });
+      return await invokeAuthed('delete_user_account', { permanent });
     } else {
       await expressClient.delete(`/users/${userId}`);
     }
@@ -301,7 +402,7 @@ export const userApi = {
 export const progressApi = {
   add: async (progressData: ProgressCreate): Promise<Progress> => {
     if (isTauriBackend()) {
-      return await invoke('add_progress', { progressData });
+      return await invokeAuthed('add_progress', { progressData });
     } else {
       return await expressClient.post('/progress', progressData);
     }
@@ -309,7 +410,7 @@ export const progressApi = {
 
   getAll: async (userId: number, filters?: any): Promise<Progress[]> => {
     if (isTauriBackend()) {
-      return await invoke('get_user_progress', { userId, ...filters });
+      return await invokeAuthed('get_user_progress', { ...filters });
     } else {
       const params = new URLSearchParams(filters).toString();
       return await expressClient.get(`/progress?${params}`);
@@ -318,7 +419,7 @@ export const progressApi = {
 
   getById: async (progressId: number): Promise<Progress> => {
     if (isTauriBackend()) {
-      return await invoke('get_user_progress_by_id', { progressId });
+      return await invokeAuthed('get_user_progress_by_id', { progressId });
     } else {
       return await expressClient.get(`/progress/${progressId}`);
     }
@@ -326,7 +427,7 @@ export const progressApi = {
 
   update: async (progressId: number, updateData: ProgressUpdate): Promise<Progress> => {
     if (isTauriBackend()) {
-      return await invoke('update_progress', { progressId, updateData });
+      return await invokeAuthed('update_progress', { progressId, updateData });
     } else {
       return await expressClient.put(`/progress/${progressId}`, updateData);
     }
@@ -334,7 +435,7 @@ export const progressApi = {
 
   delete: async (progressId: number): Promise<void> => {
     if (isTauriBackend()) {
-      return await invoke('delete_progress', { progressId });
+      return await invokeAuthed('delete_progress', { progressId });
     } else {
       await expressClient.delete(`/progress/${progressId}`);
     }
@@ -345,24 +446,24 @@ export const progressApi = {
 export const compareApi = {
   compareProgress: async (userId: number, friendId: number, filters?: any): Promise<ComparisonData> => {
     if (isTauriBackend()) {
-      return await invoke('compare_progress', { userId, friendId, ...filters });
+      return await invokeAuthed('compare_progress', { friendIds: [friendId], ...filters });
     } else {
       const params = new URLSearchParams(filters).toString();
       return await expressClient.get(`/compare/user/${friendId}?${params}`);
     }
   },
 
-  inviteFriend: async (userId: number, friendEmail: string): Promise<void> => {
+  inviteFriend: async (invite: { friendEmail?: string; friendHandle?: string }): Promise<void> => {
     if (isTauriBackend()) {
-      return await invoke('invite_friend', { userId, friendEmail });
+      return await invokeAuthed('invite_friend', invite);
     } else {
-      await expressClient.post('/compare/invite', { friendEmail });
+      await expressClient.post('/compare/invite', invite);
     }
   },
 
   getLeaderboard: async (filters?: any): Promise<LeaderboardEntry[]> => {
     if (isTauriBackend()) {
-      return await invoke('get_leaderboard', filters || {});
+      return await invokeAuthed('get_leaderboard', filters || {});
     } else {
       const params = new URLSearchParams(filters).toString();
       return await expressClient.get(`/compare/leaderboard?${params}`);
@@ -374,7 +475,7 @@ export const compareApi = {
 export const notificationApi = {
   getAll: async (userId: number, filters?: any): Promise<Notification[]> => {
     if (isTauriBackend()) {
-      return await invoke('get_notifications', { userId, ...filters });
+      return await invokeAuthed('get_notifications', { ...filters });
     } else {
       const params = new URLSearchParams(filters).toString();
       return await expressClient.get(`/notifications?${params}`);
@@ -383,7 +484,7 @@ export const notificationApi = {
 
   create: async (userId: number, notificationData: NotificationCreate): Promise<Notification> => {
     if (isTauriBackend()) {
-      return await invoke('create_notification', { userId, notificationData });
+      return await invokeAuthed('create_notification', { notificationData });
     } else {
       return await expressClient.post('/notifications', notificationData);
     }
@@ -391,7 +492,7 @@ export const notificationApi = {
 
   markAsRead: async (notificationId: number): Promise<Notification> => {
     if (isTauriBackend()) {
-      return await invoke('mark_notification_read', { notificationId });
+      return await invokeAuthed('mark_notification_read', { notificationId });
     } else {
       return await expressClient.put(`/notifications/${notificationId}/read`, {});
     }
@@ -399,7 +500,7 @@ export const notificationApi = {
 
   delete: async (notificationId: number): Promise<void> => {
     if (isTauriBackend()) {
-      return await invoke('delete_notification', { notificationId });
+      return await invokeAuthed('delete_notification', { notificationId });
     } else {
       await expressClient.delete(`/notifications/${notificationId}`);
     }
@@ -410,7 +511,7 @@ export const notificationApi = {
 export const subscriptionApi = {
   createCheckout: async (userId: number, planType: string): Promise<any> => {
     if (isTauriBackend()) {
-      return await invoke('create_checkout_session', { userId, planType });
+      return await invokeAuthed('create_checkout_session', { planType });
     } else {
       return await expressClient.post('/subscriptions/create-checkout', { planType });
     }
@@ -418,7 +519,7 @@ export const subscriptionApi = {
 
   getSubscription: async (userId: number): Promise<Subscription> => {
     if (isTauriBackend()) {
-      return await invoke('get_subscription', { userId });
+      return await invokeAuthed('get_subscription');
     } else {
       return await expressClient.get('/subscriptions');
     }
@@ -426,7 +527,7 @@ export const subscriptionApi = {
 
   cancel: async (userId: number): Promise<void> => {
     if (isTauriBackend()) {
-      return await invoke('cancel_subscription', { userId });
+      return await invokeAuthed('cancel_subscription');
     } else {
       await expressClient.post('/subscriptions/cancel', {});
     }
@@ -445,7 +546,7 @@ export const settingsApi = {
 
   update: async (key: string, value: string): Promise<Setting> => {
     if (isTauriBackend()) {
-      return await invoke('update_settings', { key, value });
+      return await invokeAuthed('update_settings', { settings: [[key, value]] });
     } else {
       return await expressClient.put(`/settings/${key}`, { value });
     }
@@ -453,7 +554,7 @@ export const settingsApi = {
 
   getMetrics: async (): Promise<Metrics> => {
     if (isTauriBackend()) {
-      return await invoke('get_metrics');
+      return await invokeAuthed('get_metrics');
     } else {
       return await expressClient.get('/settings/metrics');
     }
@@ -38,6 +38,12 @@ export interface AuthResponse {
   passwordResetRequired?: boolean;
 }
 
+// Accounts with two-factor authentication get a challenge to complete
+// with verifySecondFactor instead of a session
+export type LoginResponse =
+  | ({ status: 'authenticated' } & AuthResponse)
+  | { status: 'second_factor_required'; challengeToken: string };
+
 export interface Progress {
   id: number;
   userId: number;